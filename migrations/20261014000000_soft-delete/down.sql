@@ -0,0 +1,3 @@
+DELETE FROM banlist WHERE deleted_at IS NOT NULL;
+ALTER TABLE banlist DROP COLUMN IF EXISTS deleted_by;
+ALTER TABLE banlist DROP COLUMN IF EXISTS deleted_at;
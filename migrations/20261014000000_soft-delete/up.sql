@@ -0,0 +1,2 @@
+ALTER TABLE banlist ADD COLUMN deleted_at timestamp;
+ALTER TABLE banlist ADD COLUMN deleted_by integer references tokens (id);
@@ -0,0 +1 @@
+ALTER TABLE tokens DROP CONSTRAINT IF EXISTS tokens_token_key;
@@ -0,0 +1 @@
+ALTER TABLE tokens ADD CONSTRAINT tokens_token_key UNIQUE (token);
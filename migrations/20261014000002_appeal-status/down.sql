@@ -0,0 +1,3 @@
+ALTER TABLE banlist DROP COLUMN IF EXISTS appeal_status;
+
+DROP TYPE appeal_status;
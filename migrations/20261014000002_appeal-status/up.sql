@@ -0,0 +1,9 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'appeal_status') THEN
+        CREATE TYPE appeal_status AS ENUM ('None', 'Pending', 'Approved', 'Denied');
+    END IF;
+
+END$$;
+
+ALTER TABLE banlist ADD COLUMN appeal_status appeal_status NOT NULL DEFAULT 'None';
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS token_usage
+(
+    token integer references tokens (id) NOT NULL,
+    date  date                           NOT NULL,
+    count bigint                         NOT NULL DEFAULT 0,
+    PRIMARY KEY (token, date)
+);
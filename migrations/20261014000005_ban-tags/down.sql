@@ -0,0 +1,3 @@
+DROP INDEX IF EXISTS banlist_tags_idx;
+
+ALTER TABLE banlist DROP COLUMN IF EXISTS tags;
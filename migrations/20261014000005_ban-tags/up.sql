@@ -0,0 +1,3 @@
+ALTER TABLE banlist ADD COLUMN tags text[] NOT NULL DEFAULT '{}';
+
+CREATE INDEX IF NOT EXISTS banlist_tags_idx ON banlist USING GIN (tags);
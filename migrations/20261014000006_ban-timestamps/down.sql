@@ -0,0 +1,8 @@
+DROP TRIGGER IF EXISTS banlist_updated_at ON banlist;
+
+DROP FUNCTION IF EXISTS banlist_set_updated_at();
+
+DROP INDEX IF EXISTS banlist_updated_at_idx;
+
+ALTER TABLE banlist DROP COLUMN IF EXISTS updated_at;
+ALTER TABLE banlist DROP COLUMN IF EXISTS created_at;
@@ -0,0 +1,22 @@
+ALTER TABLE banlist ADD COLUMN created_at timestamp;
+ALTER TABLE banlist ADD COLUMN updated_at timestamp;
+
+UPDATE banlist SET created_at = date, updated_at = COALESCE(deleted_at, date);
+
+ALTER TABLE banlist
+    ALTER COLUMN created_at SET NOT NULL,
+    ALTER COLUMN created_at SET DEFAULT now(),
+    ALTER COLUMN updated_at SET NOT NULL,
+    ALTER COLUMN updated_at SET DEFAULT now();
+
+CREATE INDEX IF NOT EXISTS banlist_updated_at_idx ON banlist (updated_at);
+
+CREATE OR REPLACE FUNCTION banlist_set_updated_at() RETURNS trigger AS $$
+BEGIN
+    NEW.updated_at = now();
+    RETURN NEW;
+END$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER banlist_updated_at
+    BEFORE UPDATE ON banlist
+    FOR EACH ROW EXECUTE PROCEDURE banlist_set_updated_at();
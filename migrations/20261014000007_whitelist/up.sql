@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS whitelist
+(
+    id          bigint                         NOT NULL PRIMARY KEY,
+    admin_token integer references tokens (id) NOT NULL,
+    date        timestamp                      NOT NULL DEFAULT now()
+);
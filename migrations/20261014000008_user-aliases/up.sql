@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS user_aliases
+(
+    alias_id     bigint                         NOT NULL PRIMARY KEY,
+    canonical_id bigint                         NOT NULL,
+    admin_token  integer references tokens (id) NOT NULL,
+    CHECK (alias_id <> canonical_id)
+);
@@ -0,0 +1 @@
+ALTER TABLE banlist ALTER COLUMN date TYPE timestamptz USING date AT TIME ZONE 'UTC';
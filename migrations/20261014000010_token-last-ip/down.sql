@@ -0,0 +1,2 @@
+ALTER TABLE tokens DROP COLUMN last_ip_changed_at;
+ALTER TABLE tokens DROP COLUMN last_ip;
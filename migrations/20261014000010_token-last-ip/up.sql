@@ -0,0 +1,2 @@
+ALTER TABLE tokens ADD COLUMN last_ip inet;
+ALTER TABLE tokens ADD COLUMN last_ip_changed_at timestamp;
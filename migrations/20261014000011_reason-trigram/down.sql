@@ -0,0 +1 @@
+DROP INDEX IF EXISTS banlist_reason_trgm_idx;
@@ -0,0 +1,3 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS banlist_reason_trgm_idx ON banlist USING GIN (reason gin_trgm_ops);
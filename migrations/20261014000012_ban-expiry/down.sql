@@ -0,0 +1 @@
+ALTER TABLE banlist DROP COLUMN expires_at;
@@ -0,0 +1 @@
+ALTER TABLE banlist ADD COLUMN expires_at timestamptz;
@@ -0,0 +1,6 @@
+CREATE TABLE IF NOT EXISTS failed_auth
+(
+    source       text      NOT NULL PRIMARY KEY,
+    count        bigint    NOT NULL DEFAULT 0,
+    last_attempt timestamp NOT NULL DEFAULT now()
+);
@@ -0,0 +1 @@
+ALTER TABLE banlist ADD COLUMN IF NOT EXISTS evidence text[] NOT NULL DEFAULT '{}';
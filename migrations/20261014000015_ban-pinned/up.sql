@@ -0,0 +1 @@
+ALTER TABLE banlist ADD COLUMN IF NOT EXISTS pinned boolean NOT NULL DEFAULT false;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS banlist_language_idx;
+ALTER TABLE banlist DROP COLUMN IF EXISTS language;
@@ -0,0 +1,2 @@
+ALTER TABLE banlist ADD COLUMN IF NOT EXISTS language text;
+CREATE INDEX IF NOT EXISTS banlist_language_idx ON banlist (language);
@@ -0,0 +1,12 @@
+CREATE TABLE IF NOT EXISTS scoped_bans
+(
+    id          bigint                         NOT NULL,
+    scope_id    bigint                         NOT NULL,
+    reason      text                           NOT NULL,
+    message     text,
+    date        timestamptz                    NOT NULL DEFAULT now(),
+    admin_token integer references tokens (id) NOT NULL,
+    PRIMARY KEY (id, scope_id)
+);
+
+CREATE INDEX IF NOT EXISTS scoped_bans_scope_idx ON scoped_bans (scope_id);
@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS banlist_reason_tsv ON banlist;
+DROP FUNCTION IF EXISTS banlist_set_reason_tsv();
+DROP INDEX IF EXISTS banlist_reason_tsv_idx;
+ALTER TABLE banlist DROP COLUMN IF EXISTS reason_tsv;
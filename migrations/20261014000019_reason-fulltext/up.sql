@@ -0,0 +1,19 @@
+ALTER TABLE banlist ADD COLUMN IF NOT EXISTS reason_tsv tsvector;
+
+-- Backfilling shouldn't look like every ban changed to diff and ETag clients
+ALTER TABLE banlist DISABLE TRIGGER banlist_updated_at;
+UPDATE banlist SET reason_tsv = to_tsvector('simple', COALESCE(reason, ''));
+ALTER TABLE banlist ENABLE TRIGGER banlist_updated_at;
+
+CREATE INDEX IF NOT EXISTS banlist_reason_tsv_idx ON banlist USING GIN (reason_tsv);
+
+-- 'simple' because reasons come in many languages and shouldn't be stemmed as English
+CREATE OR REPLACE FUNCTION banlist_set_reason_tsv() RETURNS trigger AS $$
+BEGIN
+    NEW.reason_tsv = to_tsvector('simple', COALESCE(NEW.reason, ''));
+    RETURN NEW;
+END$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER banlist_reason_tsv
+    BEFORE INSERT OR UPDATE OF reason ON banlist
+    FOR EACH ROW EXECUTE PROCEDURE banlist_set_reason_tsv();
@@ -0,0 +1,24 @@
+ALTER TABLE banlist ADD COLUMN IF NOT EXISTS reason text;
+
+ALTER TABLE banlist DISABLE TRIGGER banlist_updated_at;
+UPDATE banlist b SET reason = r.text FROM reasons r WHERE r.id = b.reason_id;
+ALTER TABLE banlist ENABLE TRIGGER banlist_updated_at;
+ALTER TABLE banlist ALTER COLUMN reason SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS banlist_reason_trgm_idx ON banlist USING GIN (reason gin_trgm_ops);
+
+DROP TRIGGER IF EXISTS banlist_reason_tsv ON banlist;
+
+CREATE OR REPLACE FUNCTION banlist_set_reason_tsv() RETURNS trigger AS $$
+BEGIN
+    NEW.reason_tsv = to_tsvector('simple', COALESCE(NEW.reason, ''));
+    RETURN NEW;
+END$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER banlist_reason_tsv
+    BEFORE INSERT OR UPDATE OF reason ON banlist
+    FOR EACH ROW EXECUTE PROCEDURE banlist_set_reason_tsv();
+
+DROP INDEX IF EXISTS banlist_reason_id_idx;
+ALTER TABLE banlist DROP COLUMN IF EXISTS reason_id;
+DROP TABLE IF EXISTS reasons;
@@ -0,0 +1,44 @@
+CREATE TABLE IF NOT EXISTS reasons
+(
+    id   serial PRIMARY KEY,
+    text text NOT NULL
+);
+
+-- Reasons can be longer than a btree entry may be, so they are kept unique by their hash
+CREATE UNIQUE INDEX IF NOT EXISTS reasons_text_md5_idx ON reasons (md5(text));
+CREATE INDEX IF NOT EXISTS reasons_text_trgm_idx ON reasons USING GIN (text gin_trgm_ops);
+
+INSERT INTO reasons (text)
+SELECT DISTINCT reason FROM banlist WHERE reason IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+ALTER TABLE banlist ADD COLUMN IF NOT EXISTS reason_id integer REFERENCES reasons (id);
+
+-- Backfilling shouldn't look like every ban changed to diff and ETag clients
+ALTER TABLE banlist DISABLE TRIGGER banlist_updated_at;
+UPDATE banlist b SET reason_id = r.id FROM reasons r WHERE md5(r.text) = md5(b.reason);
+ALTER TABLE banlist ENABLE TRIGGER banlist_updated_at;
+ALTER TABLE banlist ALTER COLUMN reason_id SET NOT NULL;
+
+CREATE INDEX IF NOT EXISTS banlist_reason_id_idx ON banlist (reason_id);
+
+DROP TRIGGER IF EXISTS banlist_reason_tsv ON banlist;
+
+-- Reads the reasons table next to the banlist it fires on, so it also works for prefixed tables
+CREATE OR REPLACE FUNCTION banlist_set_reason_tsv() RETURNS trigger AS $$
+DECLARE
+    reason text;
+BEGIN
+    EXECUTE format('SELECT text FROM %I.%I WHERE id = $1',
+                   TG_TABLE_SCHEMA, left(TG_TABLE_NAME, -length('banlist')) || 'reasons')
+        INTO reason USING NEW.reason_id;
+    NEW.reason_tsv = to_tsvector('simple', COALESCE(reason, ''));
+    RETURN NEW;
+END$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER banlist_reason_tsv
+    BEFORE INSERT OR UPDATE OF reason_id ON banlist
+    FOR EACH ROW EXECUTE PROCEDURE banlist_set_reason_tsv();
+
+-- Also drops banlist_reason_trgm_idx, suggestions use the one on reasons now
+ALTER TABLE banlist DROP COLUMN reason;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS banlist_active_pinned_idx;
+DROP INDEX IF EXISTS banlist_active_expires_at_idx;
@@ -0,0 +1,4 @@
+-- now() can't be part of an index predicate, so these cover the live bans and the expiry check runs on the index.
+-- Pinned bans are always active and get their own index, so the OR in active ban queries becomes a BitmapOr
+CREATE INDEX IF NOT EXISTS banlist_active_expires_at_idx ON banlist (expires_at) WHERE deleted_at IS NULL;
+CREATE INDEX IF NOT EXISTS banlist_active_pinned_idx ON banlist (id) WHERE deleted_at IS NULL AND pinned;
@@ -0,0 +1,3 @@
+ALTER TABLE banlist DROP COLUMN IF EXISTS deleted_kind;
+
+DROP TYPE delete_kind;
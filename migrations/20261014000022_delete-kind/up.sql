@@ -0,0 +1,10 @@
+DO $$
+BEGIN
+    IF NOT EXISTS (SELECT 1 FROM pg_type WHERE typname = 'delete_kind') THEN
+        CREATE TYPE delete_kind AS ENUM ('Deleted', 'Evicted', 'Appealed');
+    END IF;
+
+END$$;
+
+-- Bans deleted before this can't be told apart, so they stay NULL and can't be undone
+ALTER TABLE banlist ADD COLUMN deleted_kind delete_kind;
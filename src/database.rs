@@ -1,33 +1,439 @@
-use chrono::NaiveDateTime;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::IpAddr;
+use std::sync::{Mutex, RwLock};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
 use postgres::{Client, Config, NoTls, Row};
-use serde::Serialize;
+use postgres::fallible_iterator::FallibleIterator;
+use postgres::error::SqlState;
+use postgres::types::ToSql;
+use postgres_types::FromSql;
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
 
 use crate::errors::UserError;
 use crate::guards::Permission;
+use crate::routes::banlist::MAX_REASON_LENGTH;
 use crate::settings;
+use crate::token_generator;
 use crate::utils;
 
+const TOKEN_CREATE_ATTEMPTS: u32 = 5;
+
+lazy_static! {
+    static ref BAN_DEDUP: Mutex<utils::DedupWindow<i64, PendingBan>> =
+        Mutex::new(utils::DedupWindow::new(Duration::from_secs(settings::ENV.general.ban_dedup_seconds)));
+    static ref BAN_BUFFER: Mutex<BanBuffer> = Mutex::new(BanBuffer::new(
+        settings::ENV.general.ban_batch_size,
+        Duration::from_millis(settings::ENV.general.ban_batch_flush_ms),
+    ));
+    // How far the database server's clock is ahead of ours, measured on the first connection
+    static ref SERVER_CLOCK_OFFSET: RwLock<Option<chrono::Duration>> = RwLock::new(None);
+}
+
+pub trait Clock: Send {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+// Follows the database server's clock, so expiry agrees with now() in SQL
+pub struct DatabaseClock;
+
+impl Clock for DatabaseClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now() + SERVER_CLOCK_OFFSET.read().unwrap().unwrap_or_else(chrono::Duration::zero)
+    }
+}
+
 pub struct Database {
     conn: Client,
+    clock: Box<dyn Clock>,
 }
 
 #[derive(Debug, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct Token {
     pub id: i32,
     pub token: String,
     pub permission: Permission,
     pub userid: i64,
     pub retired: bool,
+    #[serde(serialize_with = "utils::serialize_timestamp_opt")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<i64>"))]
+    pub last_used_at: Option<NaiveDateTime>,
+    pub last_ip: Option<IpAddr>,
+    #[serde(serialize_with = "utils::serialize_timestamp_opt")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<i64>"))]
+    pub last_ip_changed_at: Option<NaiveDateTime>,
+    // Operator note, left out of what the token holder sees
+    pub note: Option<String>,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub struct Ban {
     pub id: i64,
-    pub reason: String,
-    pub date: chrono::NaiveDateTime,
+    // Left out of every export when NULL or not selected, never sent as an empty string
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(with = "chrono::serde::ts_seconds")]
+    #[cfg_attr(feature = "schema", schemars(with = "i64"))]
+    pub date: DateTime<Utc>,
     pub admin: i32,
     pub message: Option<String>,
+    pub appeal_status: AppealStatus,
+    pub tags: Vec<String>,
+    // Links to messages or screenshots backing the ban
+    pub evidence: Vec<String>,
+    // ISO 639 code of the reason's language, if the client supplied one
+    pub language: Option<String>,
+    // Set in the database only, the API never writes it and re-banning keeps it
+    #[serde(serialize_with = "utils::serialize_datetime_opt")]
+    #[cfg_attr(feature = "schema", schemars(with = "Option<i64>"))]
+    pub expires_at: Option<DateTime<Utc>>,
+    // Never expires or gets evicted
+    pub pinned: bool,
+    // Whether the ban hasn't expired yet, computed when it's read
+    pub active: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ToSql, FromSql, Serialize, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
+#[postgres(name = "appeal_status")]
+pub enum AppealStatus {
+    None,
+    Pending,
+    Approved,
+    Denied,
+}
+
+// Parameterless queries that can be explained on demand
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+pub enum QueryOp {
+    #[serde(rename = "get_tokens")]
+    Tokens,
+    #[serde(rename = "get_bans")]
+    Bans,
+    #[serde(rename = "get_banned_ids")]
+    BannedIds,
+    #[serde(rename = "get_total_ban_count")]
+    TotalBanCount,
+    #[serde(rename = "get_active_bans")]
+    ActiveBans,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanFields {
+    // Every column of the banlist
+    #[default]
+    Full,
+    // Only id, date and admin, for listings that don't render the reason
+    Light,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanSort {
+    #[default]
+    Id,
+    Date,
+    Admin,
+}
+
+// Filters for query_bans, unset fields don't filter
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct BanQuery {
+    // Seconds since the epoch, both ends inclusive
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub tag: Option<String>,
+    pub language: Option<String>,
+    pub admin: Option<i32>,
+    pub appeal_status: Option<AppealStatus>,
+    // Matched case-insensitively anywhere in the reason
+    pub search: Option<String>,
+    pub sort: BanSort,
+    pub descending: bool,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct PermissionSummary {
+    pub user: i64,
+    pub admin: i64,
+    pub root: i64,
+    pub root_holders: Vec<i64>,
+    // Tokens whose permission this version doesn't know
+    pub unknown: i64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Page<T> {
+    pub items: Vec<T>,
+    pub total: i64,
+    pub limit: i64,
+    pub offset: i64,
+    pub has_next: bool,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    // Overwrite the existing ban
+    #[default]
+    Update,
+    // Keep the existing ban
+    Ignore,
+    // Keep the existing ban and report the conflict to the caller
+    Error,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ImportError {
+    pub line: usize,
+    pub error: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct LegacyBan {
+    pub line: usize,
+    pub id: i64,
+    pub reason: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ImportReport {
+    pub imported: u64,
+    pub errors: Vec<ImportError>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PendingBan {
+    pub id: i64,
+    pub reason: String,
+    pub admin_token: i32,
+    pub message: Option<String>,
+    pub tags: Vec<String>,
+    pub evidence: Vec<String>,
+    pub language: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum UpsertOutcome {
+    Inserted,
+    Updated,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct BanOutcome {
+    pub id: i64,
+    pub outcome: UpsertOutcome,
+}
+
+impl BanOutcome {
+    pub fn new(id: i64, inserted: bool) -> BanOutcome {
+        let outcome = if inserted { UpsertOutcome::Inserted } else { UpsertOutcome::Updated };
+        BanOutcome { id, outcome }
+    }
+}
+
+// Collects bans so they can be written in one statement once enough piled up or the oldest is due
+pub struct BanBuffer {
+    pending: Vec<PendingBan>,
+    max_size: usize,
+    interval: Duration,
+    oldest: Option<Instant>,
+}
+
+impl BanBuffer {
+    pub fn new(max_size: usize, interval: Duration) -> BanBuffer {
+        BanBuffer { pending: Vec::new(), max_size, interval, oldest: None }
+    }
+
+    pub fn push(&mut self, ban: PendingBan, now: Instant) {
+        self.oldest.get_or_insert(now);
+        self.pending.push(ban);
+    }
+
+    pub fn is_due(&self, now: Instant) -> bool {
+        match self.oldest {
+            Some(oldest) => self.pending.len() >= self.max_size || now.duration_since(oldest) >= self.interval,
+            None => false,
+        }
+    }
+
+    // Empties the buffer, keeping only the last ban per id since a batch can only write each row once
+    pub fn take(&mut self) -> Vec<PendingBan> {
+        self.oldest = None;
+        let mut bans: Vec<PendingBan> = Vec::with_capacity(self.pending.len());
+        for ban in self.pending.drain(..).rev() {
+            if !bans.iter().any(|b| b.id == ban.id) {
+                bans.push(ban);
+            }
+        }
+        bans.reverse();
+        bans
+    }
+
+    // Puts bans back in front after a failed write, so they go out with the next flush
+    pub fn requeue(&mut self, mut bans: Vec<PendingBan>, now: Instant) {
+        if bans.is_empty() {
+            return;
+        }
+        bans.append(&mut self.pending);
+        self.pending = bans;
+        self.oldest.get_or_insert(now);
+    }
+}
+
+// Changes whenever a ban or whitelist entry is written or removed
+pub fn ban_etag(latest_change: Option<NaiveDateTime>, rows: i64) -> String {
+    let micros = latest_change.map_or(0, |date| date.timestamp() * 1_000_000 + i64::from(date.timestamp_subsec_micros()));
+    format!("\"{:x}-{:x}\"", micros, rows)
+}
+
+static READ_ONLY: AtomicBool = AtomicBool::new(false);
+
+// Requests that write are refused while this is set, bookkeeping on reads is skipped instead
+pub fn read_only() -> bool {
+    READ_ONLY.load(Ordering::SeqCst)
+}
+
+pub fn set_read_only(read_only: bool) {
+    READ_ONLY.store(read_only, Ordering::SeqCst);
+}
+
+// Telegram never hands out zero or negative user ids
+pub fn is_valid_user_id(user_id: i64) -> bool {
+    user_id > 0
+}
+
+pub fn validate_user_id(user_id: i64) -> Result<(), UserError> {
+    if is_valid_user_id(user_id) {
+        Ok(())
+    } else {
+        Err(UserError::BadRequest("User ids must be positive"))
+    }
+}
+
+// Drops control characters and ANSI escape sequences, which break exports and terminals, but keeps normal whitespace
+pub fn sanitize_reason(reason: &str) -> String {
+    let mut clean = String::with_capacity(reason.len());
+    let mut chars = reason.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\u{1b}' => {
+                // CSI sequences run until a final byte in @..~
+                if chars.peek() == Some(&'[') {
+                    chars.next();
+                    for c in chars.by_ref() {
+                        if ('@'..='~').contains(&c) {
+                            break;
+                        }
+                    }
+                }
+            }
+            '\t' | '\n' | '\r' => clean.push(c),
+            c if c.is_control() => {}
+            c => clean.push(c),
+        }
+    }
+    clean
+}
+
+// True if the same ban, with every field equal, was written within ban_dedup_seconds
+pub fn is_duplicate_ban(ban: &PendingBan) -> bool {
+    if settings::ENV.general.ban_dedup_seconds == 0 {
+        return false;
+    }
+    if BAN_DEDUP.lock().unwrap().is_duplicate(&ban.id, ban, Instant::now()) {
+        debug!(utils::LOGGER, "Skipping duplicate ban"; "id" => &ban.id);
+        true
+    } else {
+        false
+    }
+}
+
+// Called once the ban is committed, so a failed write can be retried within the window
+fn record_written_bans(bans: &[PendingBan]) {
+    if settings::ENV.general.ban_dedup_seconds == 0 {
+        return;
+    }
+    let mut dedup = BAN_DEDUP.lock().unwrap();
+    let now = Instant::now();
+    for ban in bans {
+        dedup.record(ban.id, ban.clone(), now);
+    }
+}
+
+// The ban changed or is gone, writing it again isn't a duplicate anymore
+fn forget_written_bans(ids: &[i64]) {
+    let mut dedup = BAN_DEDUP.lock().unwrap();
+    for id in ids {
+        dedup.forget(id);
+    }
+}
+
+pub fn buffer_bans() -> bool {
+    settings::ENV.general.ban_batch_size > 0
+}
+
+pub fn buffer_ban(ban: PendingBan) -> bool {
+    let mut buffer = BAN_BUFFER.lock().unwrap();
+    let now = Instant::now();
+    buffer.push(ban, now);
+    buffer.is_due(now)
+}
+
+pub fn ban_buffer_due() -> bool {
+    BAN_BUFFER.lock().unwrap().is_due(Instant::now())
+}
+
+// Writes everything that is buffered. On failure the bans stay buffered
+pub fn flush_ban_buffer(db: &mut Database) -> Result<usize, postgres::Error> {
+    let bans = BAN_BUFFER.lock().unwrap().take();
+    if bans.is_empty() {
+        return Ok(0);
+    }
+    match db.add_ban_batch(&bans) {
+        Ok(_) => {
+            record_written_bans(&bans);
+            Ok(bans.len())
+        }
+        Err(e) => {
+            BAN_BUFFER.lock().unwrap().requeue(bans, Instant::now());
+            Err(e)
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub bans: Vec<Ban>,
+    // Pass this as `since` to the diff to continue from the snapshot
+    #[serde(serialize_with = "utils::serialize_timestamp_f64")]
+    pub server_time: NaiveDateTime,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct BanDiff {
+    pub added: Vec<Ban>,
+    // Bans that existed before the cursor and were updated since, e.g. a new reason
+    pub changed: Vec<Ban>,
+    pub removed: Vec<i64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConsistencyWarning {
+    pub check: &'static str,
+    pub count: i64,
 }
 
 #[derive(Debug, Serialize)]
@@ -36,27 +442,378 @@ pub struct Antiflood {
 }
 
 impl Token {
+    fn from_row(row: &Row) -> Token {
+        Token {
+            id: row.get("id"),
+            token: row.get("token"),
+            permission: row.get("permission"),
+            userid: row.get("userid"),
+            retired: row.get("retired"),
+            last_used_at: row.try_get("last_used_at").unwrap_or(None),
+            last_ip: row.try_get("last_ip").unwrap_or(None),
+            last_ip_changed_at: row.try_get("last_ip_changed_at").unwrap_or(None),
+            note: row.try_get("note").unwrap_or(None),
+        }
+    }
+
     pub fn json(&self) -> Result<Value, UserError> {
         Ok(serde_json::to_value(&self)?)
     }
+
+    // What the token's own holder gets to see
+    pub fn holder_json(&self) -> Result<Value, UserError> {
+        let mut token = self.json()?;
+        if let Some(token) = token.as_object_mut() {
+            token.remove("note");
+            token.insert("levels_to_root".to_string(), json!(self.permission.distance_to(&Permission::Root)));
+        }
+        Ok(token)
+    }
+}
+
+impl<T> Page<T> {
+    pub fn new(items: Vec<T>, total: i64, limit: i64, offset: i64) -> Page<T> {
+        let has_next = offset + (items.len() as i64) < total;
+        Page { items, total, limit, offset, has_next }
+    }
+}
+
+impl AppealStatus {
+    pub fn can_transition_to(&self, next: AppealStatus) -> bool {
+        matches!(
+            (*self, next),
+            (AppealStatus::None, AppealStatus::Pending)
+                | (AppealStatus::Denied, AppealStatus::Pending)
+                | (AppealStatus::Pending, AppealStatus::Approved)
+                | (AppealStatus::Pending, AppealStatus::Denied)
+        )
+    }
+}
+
+impl ConflictPolicy {
+    pub fn on_conflict(&self, table: &str) -> String {
+        let update = format!("
+            UPDATE SET reason_id=excluded.reason_id, date=excluded.date, message=excluded.message, tags=excluded.tags,
+                language=excluded.language,
+                evidence=CASE WHEN {0}.deleted_at IS NULL THEN {0}.evidence || excluded.evidence ELSE excluded.evidence END,
+                appeal_status=CASE WHEN {0}.deleted_at IS NULL THEN {0}.appeal_status ELSE 'None' END,
+                created_at=CASE WHEN {0}.deleted_at IS NULL THEN {0}.created_at ELSE now() END,
+                deleted_at=NULL, deleted_by=NULL, deleted_kind=NULL", table);
+        match *self {
+            ConflictPolicy::Update => format!("ON CONFLICT (id) DO {}", update),
+            // Soft-deleted bans don't count as existing, so they're still restored
+            ConflictPolicy::Ignore | ConflictPolicy::Error => {
+                format!("ON CONFLICT (id) DO {} WHERE {}.deleted_at IS NOT NULL", update, table)
+            }
+        }
+    }
+}
+
+impl PermissionSummary {
+    pub fn add(&mut self, permission: &Permission, userid: i64) {
+        match permission {
+            Permission::Unknown => self.unknown += 1,
+            Permission::User => self.user += 1,
+            Permission::Admin => self.admin += 1,
+            Permission::Root => {
+                self.root += 1;
+                self.root_holders.push(userid);
+            }
+        }
+    }
+}
+
+impl QueryOp {
+    pub fn sql(&self) -> String {
+        match *self {
+            QueryOp::Tokens => format!("SELECT * FROM {};", table("tokens")),
+            QueryOp::Bans => get_bans_query(BanFields::Full),
+            QueryOp::BannedIds => format!("
+            SELECT id FROM {} b
+            WHERE deleted_at IS NULL
+            AND NOT EXISTS (SELECT 1 FROM {} w WHERE w.id = b.id)
+            ORDER BY id;", table("banlist"), table("whitelist")),
+            QueryOp::TotalBanCount => format!("SELECT COUNT(*) FROM {} WHERE deleted_at IS NULL;", table("banlist")),
+            QueryOp::ActiveBans => active_bans_query(BanFields::Full, "now()"),
+        }
+    }
+}
+
+// Builds the same object as Ban::raw_json, the query has to provide the clock's time as clock_now
+pub const BAN_JSON_OBJECT: &str = "
+    jsonb_build_object(
+        'id', id,
+        'date', floor(extract(epoch FROM date))::bigint,
+        'admin', admin_token,
+        'message', message,
+        'appeal_status', appeal_status,
+        'tags', tags,
+        'evidence', evidence,
+        'language', language,
+        'expires_at', floor(extract(epoch FROM expires_at))::bigint,
+        'pinned', pinned,
+        'active', pinned OR expires_at IS NULL OR expires_at > clock_now
+    ) || CASE WHEN reason IS NULL THEN '{}'::jsonb ELSE jsonb_build_object('reason', reason) END";
+
+// Any of the words, as a tsquery. Only letters and digits are kept, so the input can't inject tsquery syntax
+pub fn ranked_search_terms(input: &str) -> Option<String> {
+    let mut terms: Vec<String> = Vec::new();
+    for term in input.split(|c: char| !c.is_alphanumeric()).filter(|term| !term.is_empty()) {
+        let term = term.to_lowercase();
+        if !terms.contains(&term) {
+            terms.push(term);
+        }
+    }
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" | "))
+    }
+}
+
+pub const BAN_CSV_HEADER: &str = "id,reason,date,admin,message,appeal_status,tags,language,active\n";
+
+// One line in the order of BAN_CSV_HEADER, tags are joined with ';'
+pub fn ban_csv_row(ban: &Ban) -> String {
+    format!("{},{},{},{},{},{:?},{},{},{}\n",
+            ban.id,
+            utils::csv_field(ban.reason.as_deref().unwrap_or_default()),
+            ban.date.timestamp(),
+            ban.admin,
+            utils::csv_field(ban.message.as_deref().unwrap_or_default()),
+            ban.appeal_status,
+            utils::csv_field(&ban.tags.join(";")),
+            utils::csv_field(ban.language.as_deref().unwrap_or_default()),
+            ban.active)
+}
+
+// Compact wire form of a Ban for mirrors, dates are split into seconds and nanoseconds so they survive exactly
+#[cfg(feature = "msgpack")]
+#[derive(Serialize, Deserialize)]
+struct BanRecord {
+    id: i64,
+    reason: Option<String>,
+    date: (i64, u32),
+    admin: i32,
+    message: Option<String>,
+    appeal_status: AppealStatus,
+    tags: Vec<String>,
+    evidence: Vec<String>,
+    language: Option<String>,
+    expires_at: Option<(i64, u32)>,
+    pinned: bool,
+    active: bool,
+}
+
+#[cfg(feature = "msgpack")]
+fn split_timestamp(date: DateTime<Utc>) -> (i64, u32) {
+    (date.timestamp(), date.timestamp_subsec_nanos())
+}
+
+#[cfg(feature = "msgpack")]
+fn join_timestamp((seconds, nanos): (i64, u32)) -> Result<DateTime<Utc>, UserError> {
+    NaiveDateTime::from_timestamp_opt(seconds, nanos)
+        .map(|date| DateTime::from_utc(date, Utc))
+        .ok_or(UserError::BadRequest("ban date is out of range"))
+}
+
+#[cfg(feature = "msgpack")]
+pub fn export_bans_msgpack(bans: &[Ban]) -> Result<Vec<u8>, UserError> {
+    let records: Vec<BanRecord> = bans.iter().map(|ban| BanRecord {
+        id: ban.id,
+        reason: ban.reason.clone(),
+        date: split_timestamp(ban.date),
+        admin: ban.admin,
+        message: ban.message.clone(),
+        appeal_status: ban.appeal_status,
+        tags: ban.tags.clone(),
+        evidence: ban.evidence.clone(),
+        language: ban.language.clone(),
+        expires_at: ban.expires_at.map(split_timestamp),
+        pinned: ban.pinned,
+        active: ban.active,
+    }).collect();
+    rmp_serde::to_vec(&records).map_err(|_| UserError::Internal)
+}
+
+#[cfg(feature = "msgpack")]
+pub fn import_bans_msgpack(data: &[u8]) -> Result<Vec<Ban>, UserError> {
+    let records: Vec<BanRecord> = rmp_serde::from_slice(data)
+        .map_err(|_| UserError::BadRequest("body is not a MessagePack ban export"))?;
+    records.into_iter().map(|record| Ok(Ban {
+        id: record.id,
+        reason: record.reason,
+        date: join_timestamp(record.date)?,
+        admin: record.admin,
+        message: record.message,
+        appeal_status: record.appeal_status,
+        tags: record.tags,
+        evidence: record.evidence,
+        language: record.language,
+        expires_at: record.expires_at.map(join_timestamp).transpose()?,
+        pinned: record.pinned,
+        active: record.active,
+    })).collect()
+}
+
+// Same definition of active as Ban::from_row. Backed by the partial indexes on expires_at and pinned
+pub fn active_bans_query(fields: BanFields, now: &str) -> String {
+    format!("
+        SELECT {} FROM {}
+        WHERE deleted_at IS NULL AND (pinned OR expires_at IS NULL OR expires_at > {})
+        ORDER BY id;", fields.columns(), bans_with_reasons(), now)
+}
+
+fn get_bans_query(fields: BanFields) -> String {
+    format!("SELECT {} FROM {} WHERE deleted_at IS NULL;", fields.columns(), bans_with_reasons())
+}
+
+impl BanFields {
+    pub fn columns(&self) -> &'static str {
+        match *self {
+            BanFields::Full => "*",
+            BanFields::Light => "id, date, admin_token",
+        }
+    }
+}
+
+impl BanSort {
+    pub fn column(&self) -> &'static str {
+        match *self {
+            BanSort::Id => "id",
+            BanSort::Date => "date",
+            BanSort::Admin => "admin_token",
+        }
+    }
+}
+
+impl BanQuery {
+    // Only values become parameters, the SQL itself is made of fixed fragments
+    pub fn where_clause(&self) -> (String, Vec<Box<dyn ToSql + Sync>>) {
+        let mut conditions = vec!["deleted_at IS NULL".to_string()];
+        let mut params: Vec<Box<dyn ToSql + Sync>> = Vec::new();
+        if let Some(from) = self.from {
+            params.push(Box::new(from));
+            conditions.push(format!("date >= to_timestamp(${}::bigint)", params.len()));
+        }
+        if let Some(to) = self.to {
+            params.push(Box::new(to));
+            conditions.push(format!("date <= to_timestamp(${}::bigint)", params.len()));
+        }
+        if let Some(tag) = &self.tag {
+            params.push(Box::new(tag.clone()));
+            conditions.push(format!("tags @> ARRAY[${}::text]", params.len()));
+        }
+        if let Some(language) = &self.language {
+            params.push(Box::new(language.to_ascii_lowercase()));
+            conditions.push(format!("language = ${}", params.len()));
+        }
+        if let Some(admin) = self.admin {
+            params.push(Box::new(admin));
+            conditions.push(format!("admin_token = ${}", params.len()));
+        }
+        if let Some(appeal_status) = self.appeal_status {
+            params.push(Box::new(appeal_status));
+            conditions.push(format!("appeal_status = ${}", params.len()));
+        }
+        if let Some(search) = &self.search {
+            params.push(Box::new(format!("%{}%", utils::escape_like(search))));
+            conditions.push(format!("reason ILIKE ${}", params.len()));
+        }
+        (conditions.join(" AND "), params)
+    }
+
+    pub fn order_by(&self) -> String {
+        let direction = if self.descending { "DESC" } else { "ASC" };
+        match self.sort {
+            BanSort::Id => format!("id {}", direction),
+            // Ties are broken by id so pages don't overlap
+            sort => format!("{} {}, id {}", sort.column(), direction, direction),
+        }
+    }
 }
 
 impl Ban {
+    fn from_row(row: &Row, now: DateTime<Utc>) -> Ban {
+        let expires_at: Option<DateTime<Utc>> = row.try_get("expires_at").unwrap_or(None);
+        let pinned: bool = row.try_get("pinned").unwrap_or(false);
+        Ban {
+            id: row.get("id"),
+            reason: row.try_get("reason").ok(),
+            date: row.get("date"),
+            admin: row.get("admin_token"),
+            message: row.try_get("message").unwrap_or(None),
+            appeal_status: row.try_get("appeal_status").unwrap_or(AppealStatus::None),
+            tags: row.try_get("tags").unwrap_or_default(),
+            evidence: row.try_get("evidence").unwrap_or_default(),
+            language: row.try_get("language").unwrap_or(None),
+            expires_at,
+            pinned,
+            active: pinned || is_active(expires_at, now),
+        }
+    }
+
     pub fn json(&self) -> Result<Value, UserError> {
         Ok(serde_json::to_value(self.raw_json())?)
     }
 
     pub fn raw_json(&self) -> Value {
-        json!({
+        let mut ban = json!({
             "id": self.id,
-            "reason": self.reason,
             "date": self.date.timestamp(),
             "admin": self.admin,
-            "message": self.message
-        })
+            "message": self.message,
+            "appeal_status": self.appeal_status,
+            "tags": self.tags,
+            "evidence": self.evidence,
+            "language": self.language,
+            "expires_at": self.expires_at.map(|date| date.timestamp()),
+            "pinned": self.pinned,
+            "active": self.active
+        });
+        if let Some(reason) = &self.reason {
+            ban["reason"] = json!(reason);
+        }
+        ban
+    }
+}
+
+impl PartialEq for Ban {
+    fn eq(&self, other: &Ban) -> bool {
+        self.id == other.id
     }
 }
 
+impl Eq for Ban {}
+
+impl Hash for Ban {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.id.hash(state);
+    }
+}
+
+/// Keeps the most recent ban per id, in order of first appearance. Query results are keyed on id already, this is
+/// for ban lists put together from several sources
+#[allow(dead_code)]
+pub fn dedup_bans(bans: Vec<Ban>) -> Vec<Ban> {
+    let mut positions: HashMap<i64, usize> = HashMap::new();
+    let mut deduped: Vec<Ban> = Vec::with_capacity(bans.len());
+    for ban in bans {
+        match positions.get(&ban.id) {
+            Some(&i) => {
+                if ban.date > deduped[i].date {
+                    deduped[i] = ban;
+                }
+            }
+            None => {
+                positions.insert(ban.id, deduped.len());
+                deduped.push(ban);
+            }
+        }
+    }
+    deduped
+}
+
 impl Default for Antiflood {
     fn default() -> Self {
         Antiflood {
@@ -65,13 +822,266 @@ impl Default for Antiflood {
     }
 }
 
+#[cfg(feature = "schema")]
+pub fn json_schemas() -> Value {
+    json!({
+        "Ban": schemars::schema_for!(Ban),
+        "Token": schemars::schema_for!(Token),
+    })
+}
+
+fn table(name: &str) -> String {
+    settings::ENV.database.table(name)
+}
+
+pub fn check_token(token: Option<Token>) -> Result<Token, UserError> {
+    match token {
+        Some(token) if !token.retired => Ok(token),
+        _ => Err(UserError::Unauthorized),
+    }
+}
+
+// Owners lose access while they're banned, except the genesis token so root can always recover
+pub fn check_owner_not_banned(token: Token, owner_ban: Option<&Ban>) -> Result<Token, UserError> {
+    match owner_ban {
+        Some(ban) if ban.active && token.id != 1 => Err(UserError::Forbidden),
+        _ => Ok(token),
+    }
+}
+
+// Parses the legacy `id<TAB>reason` export format, skipping blank lines and `#` comments
+pub fn parse_legacy_bans(data: &str) -> (Vec<LegacyBan>, Vec<ImportError>) {
+    let mut bans: Vec<LegacyBan> = Vec::new();
+    let mut errors: Vec<ImportError> = Vec::new();
+    for (index, line) in data.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.trim_start().starts_with('#') {
+            continue;
+        }
+        let mut fields = line.splitn(2, '\t');
+        let id = fields.next().unwrap_or_default().trim();
+        let reason = sanitize_reason(fields.next().unwrap_or_default());
+        let reason = reason.trim();
+        let error = match id.parse::<i64>() {
+            Err(_) => Some(format!("invalid id `{}`", id)),
+            Ok(parsed) if !is_valid_user_id(parsed) => Some(format!("invalid id `{}`", id)),
+            Ok(_) if reason.is_empty() => Some("missing reason".to_string()),
+            Ok(_) if reason.chars().count() > MAX_REASON_LENGTH => Some("reason is too long".to_string()),
+            Ok(id) => {
+                // Later lines win, a single upsert can't touch the same id twice
+                bans.retain(|existing| existing.id != id);
+                bans.push(LegacyBan { line: line_number, id, reason: reason.to_string() });
+                None
+            }
+        };
+        if let Some(error) = error {
+            errors.push(ImportError { line: line_number, error });
+        }
+    }
+    (bans, errors)
+}
+
+// Whitelisted ids are reported instead of imported, the ban would be hidden anyway
+pub fn reject_whitelisted(bans: Vec<LegacyBan>, whitelisted: &[i64], errors: &mut Vec<ImportError>) -> Vec<LegacyBan> {
+    let (rejected, bans): (Vec<LegacyBan>, Vec<LegacyBan>) = bans.into_iter().partition(|ban| whitelisted.contains(&ban.id));
+    errors.extend(rejected.into_iter().map(|ban| ImportError { line: ban.line, error: format!("id `{}` is whitelisted", ban.id) }));
+    errors.sort_by_key(|error| error.line);
+    bans
+}
+
+pub fn loggable_query(query: &str, log_queries: bool) -> &str {
+    if log_queries {
+        query
+    } else {
+        "<hidden>"
+    }
+}
+
+fn log_query(query: &str) -> &str {
+    loggable_query(query, settings::ENV.database.log_queries)
+}
+
+// Soft-deletes the oldest bans beyond max_bans, never touching the ones in `keep`
+fn evict_over_cap(transaction: &mut postgres::Transaction, keep: &[i64], admin_token: i32,
+                  max_bans: u64) -> Result<(), postgres::Error> {
+    if max_bans == 0 {
+        return Ok(());
+    }
+    let count_bans = format!("SELECT COUNT(*) FROM {} WHERE deleted_at IS NULL;", table("banlist"));
+    let total: i64 = transaction.query_one(count_bans.as_str(), &[])?.get(0);
+    let excess = bans_over_cap(total, max_bans);
+    if excess > 0 {
+        let evict_bans = evict_bans_query();
+        debug!(utils::LOGGER, "Evicting oldest bans";
+            "count" => excess, "query" => log_query(&evict_bans));
+        transaction.execute(evict_bans.as_str(), &[&keep, &admin_token, &excess])?;
+    }
+    Ok(())
+}
+
+// Soft-deletes the $3 oldest bans that aren't pinned or in $1
+pub fn evict_bans_query() -> String {
+    format!("
+        UPDATE {0}
+        SET deleted_at = now(), deleted_by = $2, deleted_kind = 'Evicted'
+        WHERE id IN (
+            SELECT id FROM {0}
+            WHERE deleted_at IS NULL AND NOT pinned AND id <> ALL($1)
+            ORDER BY date, id
+            LIMIT $3
+        );", table("banlist"))
+}
+
+// Writes the ban, $2 is the id of its row in reasons
+pub fn upsert_ban_query(policy: ConflictPolicy) -> String {
+    format!("
+        INSERT INTO {} (id, reason_id, date, admin_token, message, tags, evidence, language)
+        VALUES ($1, $2, now(), $3, $4, $5, $6, $7)
+        {};", table("banlist"), policy.on_conflict(&table("banlist")))
+}
+
+// Bans with their reason text, for reads. Built here instead of as a view so it follows table_prefix
+fn bans_with_reasons() -> String {
+    format!("(SELECT b.*, r.text AS reason FROM {} b LEFT JOIN {} r ON r.id = b.reason_id) bans",
+            table("banlist"), table("reasons"))
+}
+
+// Each text once, a single upsert can't touch the same reasons row twice
+pub fn distinct_reasons(reasons: &[String]) -> Vec<&str> {
+    let mut distinct: Vec<&str> = Vec::new();
+    for reason in reasons {
+        if !distinct.contains(&reason.as_str()) {
+            distinct.push(reason);
+        }
+    }
+    distinct
+}
+
+// The id of the stored row for each reason, bans with the same text share one.
+// None only if the upsert didn't return the text, which the NOT NULL on reason_id then refuses
+pub fn reason_ids_for(reasons: &[String], stored: &[(i32, String)]) -> Vec<Option<i32>> {
+    let ids: HashMap<&str, i32> = stored.iter().map(|(id, text)| (text.as_str(), *id)).collect();
+    reasons.iter().map(|reason| ids.get(reason.as_str()).cloned()).collect()
+}
+
+// Ids of the reasons rows for `reasons`, in the same order, inserting the texts that are new
+fn upsert_reasons(transaction: &mut postgres::Transaction, reasons: &[String]) -> Result<Vec<Option<i32>>, postgres::Error> {
+    // The no-op update makes existing rows show up in RETURNING too
+    let upsert_reasons = format!("
+        INSERT INTO {0} (text) SELECT unnest($1::text[])
+        ON CONFLICT (md5(text)) DO UPDATE SET text = {0}.text
+        RETURNING id, text;", table("reasons"));
+    debug!(utils::LOGGER, "Upserting reasons";
+        "count" => reasons.len(), "query" => log_query(&upsert_reasons));
+    let stored: Vec<(i32, String)> = transaction.query(upsert_reasons.as_str(), &[&distinct_reasons(reasons)])?
+        .iter()
+        .map(|row| (row.get(0), row.get(1)))
+        .collect();
+    Ok(reason_ids_for(reasons, &stored))
+}
+
+pub fn bans_over_cap(total: i64, max_bans: u64) -> i64 {
+    if max_bans == 0 {
+        return 0;
+    }
+    (total - max_bans as i64).max(0)
+}
+
+// Operations that can be switched off with disabled_operations. Every write a client asks for is covered by one of
+// them, startup seeding and usage bookkeeping aren't. add_ban
+// also stops imports, reason updates, evidence, pins, undo, aliases and scoped bans, create_token also stops rotation
+// and update_token also stops moving the master token
+pub const OPERATIONS: &[&str] = &["create_token", "revoke_token", "update_token", "add_ban", "delete_ban",
+    "set_appeal_status", "add_whitelist", "remove_whitelist"];
+
+pub fn check_operation(disabled: &[String], operation: &str) -> Result<(), UserError> {
+    if disabled.iter().any(|name| name == operation) {
+        Err(UserError::Forbidden)
+    } else {
+        Ok(())
+    }
+}
+
+pub fn check_writable(read_only: bool) -> Result<(), UserError> {
+    if read_only {
+        Err(UserError::ServiceReadOnly)
+    } else {
+        Ok(())
+    }
+}
+
+// The HTTP gate already refuses writes in read-only mode, this also covers callers that don't go through it
+pub fn ensure_writable() -> Result<(), UserError> {
+    check_writable(read_only())
+}
+
+// Called first thing by the operations in OPERATIONS, before any query runs
+pub fn ensure_enabled(operation: &str) -> Result<(), UserError> {
+    ensure_writable()?;
+    check_operation(&settings::ENV.disabled_operations, operation)
+}
+
+pub fn is_active(expires_at: Option<DateTime<Utc>>, now: DateTime<Utc>) -> bool {
+    match expires_at {
+        Some(expires_at) => expires_at > now,
+        None => true,
+    }
+}
+
+pub fn trips_auth_threshold(attempts: i64, threshold: u64) -> bool {
+    threshold > 0 && attempts >= threshold as i64
+}
+
+/// A token moved if it was seen from one address before and now shows up from another
+pub fn ip_changed(previous: Option<IpAddr>, current: Option<IpAddr>) -> bool {
+    match (previous, current) {
+        (Some(previous), Some(current)) => previous != current,
+        _ => false,
+    }
+}
+
+/// Seeds that have no active token yet. Fixed tokens match on the token, others on userid and permission
+pub fn missing_seeds<'a>(seeds: &'a [settings::SeedToken], existing: &[Token]) -> Vec<&'a settings::SeedToken> {
+    seeds
+        .iter()
+        .filter(|seed| !existing.iter().any(|token| {
+            !token.retired && match &seed.token {
+                Some(fixed) => &token.token == fixed,
+                None => token.userid == seed.userid && token.permission == seed.permission,
+            }
+        }))
+        .collect()
+}
+
+fn is_unique_violation(error: &postgres::Error) -> bool {
+    error.code() == Some(&SqlState::UNIQUE_VIOLATION)
+}
+
+/// Wraps an ad-hoc query so all of its rows come back as a single JSON array
+pub fn readonly_query_wrapper(sql: &str) -> String {
+    format!("SELECT COALESCE(json_agg(t), '[]')::text FROM ({}) t;", sql.trim().trim_end_matches(';'))
+}
+
+pub fn is_slow(elapsed: Duration, threshold_ms: u64) -> bool {
+    threshold_ms > 0 && elapsed >= Duration::from_millis(threshold_ms)
+}
+
+fn log_if_slow(operation: &str, elapsed: Duration) {
+    if is_slow(elapsed, settings::ENV.database.slow_query_ms) {
+        warn!(utils::LOGGER, "Slow query";
+            "operation" => operation, "duration_ms" => elapsed.as_millis() as u64);
+    }
+}
+
 impl Database {
     pub fn new() -> Result<Database, postgres::Error> {
+        Database::with_clock(Box::new(DatabaseClock))
+    }
+
+    pub fn with_clock(clock: Box<dyn Clock>) -> Result<Database, postgres::Error> {
         debug!(utils::LOGGER, "Connecting to database";
-         "host" => &settings::ENV.database.host,
-         "port" => settings::ENV.database.port,
-         "name" => &settings::ENV.database.name,
-         "username" => &settings::ENV.database.username);
+         "dsn" => settings::ENV.database.masked_dsn());
         let conn = Config::new()
             .host(&settings::ENV.database.host)
             .port(settings::ENV.database.port)
@@ -81,18 +1091,47 @@ impl Database {
             .application_name(&env!("CARGO_PKG_NAME"))
             .connect(NoTls)?;
         debug!(utils::LOGGER, "Connected to PostgreSQL");
-        Ok(Database { conn })
+        let mut db = Database { conn, clock };
+        if SERVER_CLOCK_OFFSET.read().unwrap().is_none() {
+            let server_time: DateTime<Utc> = db.conn.query_one("SELECT now();", &[])?.get(0);
+            *SERVER_CLOCK_OFFSET.write().unwrap() = Some(server_time - Utc::now());
+        }
+        Ok(db)
+    }
+
+    pub fn now(&self) -> DateTime<Utc> {
+        self.clock.now()
+    }
+
+    // Consumes the connection so it can't be used after the session is terminated
+    pub fn close(self) {
+        debug!(utils::LOGGER, "Closing connection to PostgreSQL");
+        drop(self.conn);
+    }
+
+    fn query(&mut self, operation: &str, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<Vec<Row>, postgres::Error> {
+        let start = Instant::now();
+        let result = self.conn.query(query, params);
+        log_if_slow(operation, start.elapsed());
+        result
+    }
+
+    fn execute(&mut self, operation: &str, query: &str, params: &[&(dyn ToSql + Sync)]) -> Result<u64, postgres::Error> {
+        let start = Instant::now();
+        let result = self.conn.execute(query, params);
+        log_if_slow(operation, start.elapsed());
+        result
     }
 
     //region Tokens
     pub fn create_genesis_token(&mut self) -> Result<(), postgres::Error> {
-        let get_genesis_token = "SELECT * FROM tokens WHERE id = 1;";
+        let get_genesis_token = format!("SELECT * FROM {} WHERE id = 1;", table("tokens"));
         debug!(utils::LOGGER, "Checking if Genesis Token exists";
-            "query" => get_genesis_token);
-        if self.conn.query(get_genesis_token, &[])?.is_empty() {
+            "query" => log_query(&get_genesis_token));
+        if self.query("create_genesis_token", &get_genesis_token, &[])?.is_empty() {
             info!(utils::LOGGER, "Genesis Token doesn't exist. Creating one";
                 "size" => settings::ENV.general.token_size);
-            let token = self.create_token(&Permission::Root, settings::ENV.general.masterid)?;
+            let token = self.insert_token(&Permission::Root, settings::ENV.general.masterid, None)?;
             info!(utils::LOGGER, "Created Genesis Token `{}`. Write this down, this will be the only time you see it.", token)
         } else {
             debug!(utils::LOGGER, "Genesis Token exists. Skipping creation.")
@@ -101,122 +1140,603 @@ impl Database {
     }
 
     pub fn get_tokens(&mut self) -> Result<Vec<Token>, postgres::Error> {
-        let get_all_tokens = "SELECT * FROM tokens;";
-        debug!(utils::LOGGER, "Getting all tokens"; "query" => get_all_tokens);
-        let result: Vec<Row> = self.conn.query(get_all_tokens, &[])?;
+        let get_all_tokens = QueryOp::Tokens.sql();
+        debug!(utils::LOGGER, "Getting all tokens"; "query" => log_query(&get_all_tokens));
+        let result: Vec<Row> = self.query("get_tokens", &get_all_tokens, &[])?;
         Ok(result
-            .into_iter()
-            .map(|row| Token {
-                id: row.get(0),
-                token: row.get(1),
-                permission: row.get(2),
-                userid: row.get(3),
-                retired: row.get(4),
-            })
+            .iter()
+            .map(Token::from_row)
             .collect())
     }
 
+    pub fn permission_summary(&mut self) -> Result<PermissionSummary, postgres::Error> {
+        let get_permissions = format!("SELECT permission, userid FROM {} WHERE NOT retired ORDER BY id;", table("tokens"));
+        debug!(utils::LOGGER, "Getting permission summary"; "query" => log_query(&get_permissions));
+        let result: Vec<Row> = self.query("permission_summary", &get_permissions, &[])?;
+        let mut summary = PermissionSummary::default();
+        for row in &result {
+            summary.add(&row.get(0), row.get(1));
+        }
+        Ok(summary)
+    }
+
     pub fn get_token_by_id(&mut self, token_id: i32) -> Result<Option<Token>, postgres::Error> {
-        let get_token_by_id = "SELECT * FROM tokens WHERE id = $1;";
+        let get_token_by_id = format!("SELECT * FROM {} WHERE id = $1;", table("tokens"));
         debug!(utils::LOGGER, "Getting token by id";
-            "id" => token_id, "query" => get_token_by_id);
-        let row: Option<Row> = self.conn.query(get_token_by_id, &[&token_id])?.pop();
+            "id" => token_id, "query" => log_query(&get_token_by_id));
+        let row: Option<Row> = self.query("get_token_by_id", &get_token_by_id, &[&token_id])?.pop();
 
-        Ok(match row {
-            Some(token) => Some(Token {
-                id: token.get(0),
-                token: token.get(1),
-                permission: token.get(2),
-                userid: token.get(3),
-                retired: token.get(4),
-            }),
-            None => None,
-        })
+        Ok(row.as_ref().map(Token::from_row))
+    }
+
+    pub fn get_tokens_by_ids(&mut self, ids: &[i32]) -> Result<Vec<Token>, postgres::Error> {
+        // Tokens are returned in the same order as the requested ids
+        let get_tokens_by_ids = format!("
+            SELECT * FROM {}
+            WHERE id = ANY($1)
+            ORDER BY array_position($1, id);", table("tokens"));
+        debug!(utils::LOGGER, "Getting tokens by ids";
+            "count" => ids.len(), "query" => log_query(&get_tokens_by_ids));
+        let result: Vec<Row> = self.query("get_tokens_by_ids", &get_tokens_by_ids, &[&ids])?;
+        Ok(result
+            .iter()
+            .map(Token::from_row)
+            .collect())
     }
 
     pub fn get_token_by_userid(&mut self, userid: i64) -> Result<Vec<Token>, postgres::Error> {
-        let get_token_by_id = "SELECT * FROM tokens WHERE userid = $1;";
+        let get_token_by_id = format!("SELECT * FROM {} WHERE userid = $1;", table("tokens"));
         debug!(utils::LOGGER, "Getting token by userid";
-            "id" => userid, "query" => get_token_by_id);
-        let result: Vec<Row> = self.conn.query(get_token_by_id, &[&userid])?;
+            "id" => userid, "query" => log_query(&get_token_by_id));
+        let result: Vec<Row> = self.query("get_token_by_userid", &get_token_by_id, &[&userid])?;
 
         Ok(result
-            .into_iter()
-            .map(|row| Token {
-                id: row.get(0),
-                token: row.get(1),
-                permission: row.get(2),
-                userid: row.get(3),
-                retired: row.get(4),
-            })
+            .iter()
+            .map(Token::from_row)
             .collect())
     }
 
     pub fn get_token(&mut self, token: String) -> Result<Option<Token>, postgres::Error> {
-        let get_token_by_id = "SELECT * FROM tokens WHERE token = $1;";
-        debug!(utils::LOGGER, "Getting token"; "query" => get_token_by_id);
-        let row: Option<Row> = self.conn.query(get_token_by_id, &[&token])?.pop();
+        let get_token_by_id = format!("SELECT * FROM {} WHERE token = $1;", table("tokens"));
+        debug!(utils::LOGGER, "Getting token"; "query" => log_query(&get_token_by_id));
+        let row: Option<Row> = self.query("get_token", &get_token_by_id, &[&token])?.pop();
 
-        Ok(match row {
-            Some(token) => Some(Token {
-                id: token.get(0),
-                token: token.get(1),
-                permission: token.get(2),
-                userid: token.get(3),
-                retired: token.get(4),
-            }),
-            None => None,
-        })
+        Ok(row.as_ref().map(Token::from_row))
+    }
+
+    pub fn authenticate(&mut self, raw_token: &str, ip: Option<IpAddr>) -> Result<Token, UserError> {
+        if raw_token.is_empty() || !token_generator::is_plausible(raw_token) {
+            return Err(UserError::Unauthorized);
+        }
+        let token = self.get_token(raw_token.to_string())?;
+        if token.is_none() {
+            let source = ip.map_or_else(|| "unknown".to_string(), |ip| ip.to_string());
+            let attempts = self.record_failed_auth(&source)?;
+            if trips_auth_threshold(attempts, settings::ENV.general.failed_auth_threshold) {
+                warn!(utils::LOGGER, "Repeated authentication with unknown tokens";
+                    "source" => source, "attempts" => attempts);
+            }
+        }
+        let token = check_token(token)?;
+        let (id, userid) = (token.id, token.userid);
+        let owner_ban = self.get_ban(userid)?;
+        let token = match check_owner_not_banned(token, owner_ban.as_ref()) {
+            Ok(token) => token,
+            Err(err) => {
+                warn!(utils::LOGGER, "Rejected token of a banned owner"; "id" => id, "userid" => userid);
+                return Err(err);
+            }
+        };
+        if read_only() {
+            return Ok(token);
+        }
+        if ip_changed(token.last_ip, ip) {
+            warn!(utils::LOGGER, "Token used from a new address";
+                "id" => token.id, "userid" => token.userid,
+                "previous" => token.last_ip.map(|ip| ip.to_string()),
+                "current" => ip.map(|ip| ip.to_string()));
+        }
+        let touch_token = format!("
+            UPDATE {} SET
+                last_used_at = now(),
+                last_ip_changed_at = CASE WHEN last_ip IS DISTINCT FROM $2 AND $2 IS NOT NULL
+                    THEN now() ELSE last_ip_changed_at END,
+                last_ip = COALESCE($2, last_ip)
+            WHERE id = $1;", table("tokens"));
+        debug!(utils::LOGGER, "Touching token";
+            "id" => token.id, "query" => log_query(&touch_token));
+        self.execute("authenticate", &touch_token, &[&token.id, &ip])?;
+        Ok(token)
+    }
+
+    fn record_failed_auth(&mut self, source: &str) -> Result<i64, postgres::Error> {
+        if read_only() {
+            return Ok(0);
+        }
+        let record_failed_auth = format!("
+            INSERT INTO {0} (source, count)
+            VALUES ($1, 1)
+            ON CONFLICT (source) DO
+            UPDATE SET count = {0}.count + 1, last_attempt = now()
+            RETURNING count;", table("failed_auth"));
+        debug!(utils::LOGGER, "Recording failed authentication";
+            "source" => source, "query" => log_query(&record_failed_auth));
+        let rows = self.query("record_failed_auth", &record_failed_auth, &[&source])?;
+        Ok(rows[0].get(0))
     }
 
     pub fn create_token(
         &mut self,
         permission: &Permission,
         userid: i64,
+        note: Option<&str>,
+    ) -> Result<String, UserError> {
+        ensure_enabled("create_token")?;
+        Ok(self.insert_token(permission, userid, note)?)
+    }
+
+    // Startup tokens are configured by the operator, so they're written even if create_token is disabled
+    fn insert_token(
+        &mut self,
+        permission: &Permission,
+        userid: i64,
+        note: Option<&str>,
     ) -> Result<String, postgres::Error> {
-        let token = nanoid::generate(settings::ENV.general.token_size as usize);
-        let insert_token = "
-            INSERT INTO tokens (
+        let insert_token = format!("
+            INSERT INTO {} (
                 token,
                 permission,
-                userid)
-            VALUES ($1, $2, $3);";
-        debug!(utils::LOGGER, "Creating Token";
-         "query" => insert_token, "permission" => format!("{:?}", permission));
-        self.conn.execute(insert_token, &[&token, &permission, &userid])?;
+                userid,
+                note)
+            VALUES ($1, $2, $3, $4);", table("tokens"));
+        utils::retry(TOKEN_CREATE_ATTEMPTS, is_unique_violation, || {
+            let token = token_generator::generate(permission);
+            debug!(utils::LOGGER, "Creating Token";
+             "query" => log_query(&insert_token), "permission" => format!("{:?}", permission));
+            self.execute("create_token", &insert_token, &[&token, &permission, &userid, &note])?;
+            Ok(token)
+        })
+    }
+
+    pub fn create_default_token(&mut self, userid: i64, note: Option<&str>) -> Result<String, UserError> {
+        // validate() refuses to start with an invalid default, so this only fails if that was skipped
+        let permission = settings::parse_permission(&settings::ENV.general.default_permission)
+            .ok_or(UserError::Internal)?;
+        self.create_token(&permission, userid, note)
+    }
+
+    pub fn seed_tokens(&mut self) -> Result<usize, postgres::Error> {
+        let existing = self.get_tokens()?;
+        let missing = missing_seeds(&settings::ENV.seed_tokens, &existing);
+        let insert_token = format!("
+            INSERT INTO {} (token, permission, userid)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (token) DO NOTHING;", table("tokens"));
+        for seed in missing.iter() {
+            debug!(utils::LOGGER, "Seeding token";
+                "userid" => seed.userid, "permission" => format!("{:?}", seed.permission));
+            match &seed.token {
+                Some(token) => {
+                    self.execute("seed_tokens", &insert_token, &[token, &seed.permission, &seed.userid])?;
+                }
+                None => {
+                    self.insert_token(&seed.permission, seed.userid, None)?;
+                }
+            }
+        }
+        Ok(missing.len())
+    }
+
+    pub fn rotate_token(&mut self, token_id: i32) -> Result<String, UserError> {
+        ensure_enabled("create_token")?;
+        let rotate_token = format!("UPDATE {} SET token = $1 WHERE id = $2 AND NOT retired;", table("tokens"));
+        // The new token keeps the prefix of the permission
+        let permission = match self.get_token_by_id(token_id)? {
+            Some(token) if !token.retired => token.permission,
+            _ => return Err(UserError::NotFound),
+        };
+        let (token, updated) = utils::retry(TOKEN_CREATE_ATTEMPTS, is_unique_violation, || {
+            let token = token_generator::generate(&permission);
+            debug!(utils::LOGGER, "Rotating token";
+                "id" => token_id, "query" => log_query(&rotate_token));
+            let updated = self.execute("rotate_token", &rotate_token, &[&token, &token_id])?;
+            Ok((token, updated))
+        })?;
+        // Revoked in the meantime, the new secret was never stored
+        if updated == 0 {
+            return Err(UserError::NotFound);
+        }
         Ok(token)
     }
 
-    pub fn revoke_token_by_id(&mut self, token_id: i32) -> Result<(), postgres::Error> {
-        let revoke_token_by_id = "UPDATE tokens SET retired = true WHERE id = $1;";
-        debug!(utils::LOGGER, "Revoking token by id";
-            "id" => token_id, "query" => revoke_token_by_id);
-        self.conn.query(revoke_token_by_id, &[&token_id])?;
+    // Returns false if there is no such token, None clears the note
+    pub fn set_token_note(&mut self, token_id: i32, note: Option<&str>) -> Result<bool, UserError> {
+        ensure_enabled("update_token")?;
+        let set_token_note = format!("UPDATE {} SET note = $2 WHERE id = $1;", table("tokens"));
+        debug!(utils::LOGGER, "Setting token note";
+            "id" => token_id, "query" => log_query(&set_token_note));
+        Ok(self.execute("set_token_note", &set_token_note, &[&token_id, &note])? > 0)
+    }
+
+    // The genesis token keeps its permission, so there is always a root token to recover with
+    pub fn update_permissions_by_userid(&mut self, userid: i64, permission: &Permission) -> Result<u64, UserError> {
+        ensure_enabled("update_token")?;
+        let update_permissions = format!("
+            UPDATE {}
+            SET permission = $2
+            WHERE userid = $1 AND id <> 1;", table("tokens"));
+        debug!(utils::LOGGER, "Updating permissions by userid";
+            "userid" => userid, "permission" => format!("{:?}", permission), "query" => log_query(&update_permissions));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        let updated = transaction.execute(update_permissions.as_str(), &[&userid, permission])?;
+        transaction.commit()?;
+        log_if_slow("update_permissions_by_userid", start.elapsed());
+        Ok(updated)
+    }
+
+    // Moves the genesis token to a new Telegram account, nothing else is touched
+    pub fn reassign_master(&mut self, new_master_id: i64) -> Result<(), UserError> {
+        ensure_enabled("update_token")?;
+        validate_user_id(new_master_id)?;
+        let lock_genesis = format!("SELECT userid FROM {} WHERE id = 1 FOR UPDATE;", table("tokens"));
+        let reassign_master = format!("UPDATE {} SET userid = $1 WHERE id = 1;", table("tokens"));
+        debug!(utils::LOGGER, "Reassigning master";
+            "userid" => new_master_id, "query" => log_query(&reassign_master));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        let previous: i64 = match transaction.query(lock_genesis.as_str(), &[])?.first() {
+            Some(row) => row.get(0),
+            None => return Err(UserError::NotFound),
+        };
+        transaction.execute(reassign_master.as_str(), &[&new_master_id])?;
+        transaction.commit()?;
+        log_if_slow("reassign_master", start.elapsed());
+        info!(utils::LOGGER, "Reassigned genesis token"; "from" => previous, "to" => new_master_id);
         Ok(())
     }
+
+    // Returns false if the token was already revoked, so retries are harmless
+    pub fn revoke_token_by_id(&mut self, token_id: i32) -> Result<bool, UserError> {
+        ensure_enabled("revoke_token")?;
+        let revoke_token_by_id = format!("UPDATE {} SET retired = true WHERE id = $1 AND NOT retired;", table("tokens"));
+        debug!(utils::LOGGER, "Revoking token by id";
+            "id" => token_id, "query" => log_query(&revoke_token_by_id));
+        Ok(self.execute("revoke_token_by_id", &revoke_token_by_id, &[&token_id])? > 0)
+    }
     //endregion
 
     //region Banlist
-    pub fn get_bans(&mut self) -> Result<Vec<Ban>, postgres::Error> {
-        let get_all_bans = "SELECT * FROM banlist;";
-        debug!(utils::LOGGER, "Getting all bans"; "query" => get_all_bans);
-        let result: Vec<Row> = self.conn.query(get_all_bans, &[])?;
+    pub fn get_bans(&mut self, fields: BanFields) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_all_bans = get_bans_query(fields);
+        debug!(utils::LOGGER, "Getting all bans"; "query" => log_query(&get_all_bans));
+        let result: Vec<Row> = self.query("get_bans", &get_all_bans, &[])?;
         Ok(result
-            .into_iter()
-            .map(|row| Ban {
-                id: row.get(0),
-                reason: row.get(1),
-                date: row.get(2),
-                admin: row.get(3),
-                message: row.try_get(4).unwrap_or(Some("test".to_string())),
-            })
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn get_active_bans(&mut self, fields: BanFields) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_active_bans = active_bans_query(fields, "$1::timestamptz");
+        debug!(utils::LOGGER, "Getting active bans"; "query" => log_query(&get_active_bans));
+        let result: Vec<Row> = self.query("get_active_bans", &get_active_bans, &[&now])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn get_bans_after(&mut self, fields: BanFields, after_id: i64, limit: i64) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_bans_after = format!("
+            SELECT {} FROM {}
+            WHERE id > $1 AND deleted_at IS NULL
+            ORDER BY id
+            LIMIT $2;", fields.columns(), bans_with_reasons());
+        debug!(utils::LOGGER, "Getting bans after id";
+            "after" => after_id, "limit" => limit, "query" => log_query(&get_bans_after));
+        let result: Vec<Row> = self.query("get_bans_after", &get_bans_after, &[&after_id, &limit])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn get_bans_page(&mut self, fields: BanFields, limit: i64, offset: i64) -> Result<Page<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_bans_page = format!("
+            SELECT {}, COUNT(*) OVER () AS total FROM {}
+            WHERE deleted_at IS NULL
+            ORDER BY id
+            LIMIT $1 OFFSET $2;", fields.columns(), bans_with_reasons());
+        debug!(utils::LOGGER, "Getting page of bans";
+            "limit" => limit, "offset" => offset, "query" => log_query(&get_bans_page));
+        let result: Vec<Row> = self.query("get_bans_page", &get_bans_page, &[&limit, &offset])?;
+        // The window count is only available if the page has rows
+        let total = match result.first() {
+            Some(row) => row.get("total"),
+            None if offset > 0 => self.get_total_ban_count()?,
+            None => 0,
+        };
+        let bans = result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect();
+        Ok(Page::new(bans, total, limit, offset))
+    }
+
+    pub fn get_bans_json(&mut self, limit: i64, offset: i64) -> Result<Value, postgres::Error> {
+        let now = self.now();
+        let get_bans_json = format!("
+            SELECT COALESCE(jsonb_agg(ban ORDER BY id), '[]')::text FROM (
+                SELECT id, {} AS ban FROM {}, (SELECT $3::timestamptz AS clock_now) c
+                WHERE deleted_at IS NULL
+                ORDER BY id
+                LIMIT $1 OFFSET $2
+            ) b;", BAN_JSON_OBJECT, bans_with_reasons());
+        debug!(utils::LOGGER, "Getting bans as json";
+            "limit" => limit, "offset" => offset, "query" => log_query(&get_bans_json));
+        let result: Vec<Row> = self.query("get_bans_json", &get_bans_json, &[&limit, &offset, &now])?;
+        let json: String = result[0].get(0);
+        // Postgres only produces valid JSON here
+        Ok(serde_json::from_str(&json).unwrap_or(Value::Array(vec![])))
+    }
+
+    pub fn get_bans_excluding(&mut self, fields: BanFields, ids: &[i64], limit: i64, offset: i64) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_bans_excluding = format!("
+            SELECT {} FROM {}
+            WHERE id <> ALL($1) AND deleted_at IS NULL
+            ORDER BY id
+            LIMIT $2 OFFSET $3;", fields.columns(), bans_with_reasons());
+        debug!(utils::LOGGER, "Getting bans excluding ids";
+            "excluded" => ids.len(), "limit" => limit, "offset" => offset, "query" => log_query(&get_bans_excluding));
+        let result: Vec<Row> = self.query("get_bans_excluding", &get_bans_excluding, &[&ids, &limit, &offset])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn get_bans_by_tag(&mut self, fields: BanFields, tag: &str) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_bans_by_tag = format!("
+            SELECT {} FROM {}
+            WHERE tags @> ARRAY[$1] AND deleted_at IS NULL;", fields.columns(), bans_with_reasons());
+        debug!(utils::LOGGER, "Getting bans by tag";
+            "tag" => tag, "query" => log_query(&get_bans_by_tag));
+        let result: Vec<Row> = self.query("get_bans_by_tag", &get_bans_by_tag, &[&tag])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn get_bans_by_language(&mut self, fields: BanFields, language: &str) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_bans_by_language = format!("
+            SELECT {} FROM {}
+            WHERE language = $1 AND deleted_at IS NULL;", fields.columns(), bans_with_reasons());
+        debug!(utils::LOGGER, "Getting bans by language";
+            "language" => language, "query" => log_query(&get_bans_by_language));
+        let result: Vec<Row> = self.query("get_bans_by_language", &get_bans_by_language, &[&language])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn query_bans(&mut self, query: &BanQuery, limit: i64, offset: i64) -> Result<Page<Ban>, postgres::Error> {
+        let now = self.now();
+        let (conditions, mut params) = query.where_clause();
+        let count_bans = format!("SELECT COUNT(*) FROM {} WHERE {};", bans_with_reasons(), conditions);
+        let query_bans = format!("
+            SELECT *, COUNT(*) OVER () AS total FROM {}
+            WHERE {}
+            ORDER BY {}
+            LIMIT ${} OFFSET ${};",
+            bans_with_reasons(), conditions, query.order_by(), params.len() + 1, params.len() + 2);
+        debug!(utils::LOGGER, "Querying bans";
+            "limit" => limit, "offset" => offset, "query" => log_query(&query_bans));
+        params.push(Box::new(limit));
+        params.push(Box::new(offset));
+        let refs: Vec<&(dyn ToSql + Sync)> = params.iter().map(|param| param.as_ref()).collect();
+        let result: Vec<Row> = self.query("query_bans", &query_bans, &refs)?;
+        // The window count is only available if the page has rows
+        let total = match result.first() {
+            Some(row) => row.get("total"),
+            None if offset > 0 => self.query("query_bans_count", &count_bans, &refs[..refs.len() - 2])?[0].get(0),
+            None => 0,
+        };
+        let bans = result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect();
+        Ok(Page::new(bans, total, limit, offset))
+    }
+
+    // Hands out CSV as the rows come in, so the export is never held in memory as a whole
+    pub fn export_bans_csv_query<F>(&mut self, query: &BanQuery, mut emit: F) -> Result<u64, UserError>
+        where F: FnMut(String) -> Result<(), UserError> {
+        let now = self.now();
+        let (conditions, params) = query.where_clause();
+        let export_bans = format!("SELECT * FROM {} WHERE {} ORDER BY {};",
+                                  bans_with_reasons(), conditions, query.order_by());
+        debug!(utils::LOGGER, "Exporting bans as CSV"; "query" => log_query(&export_bans));
+        let start = Instant::now();
+        let mut rows = self.conn.query_raw(export_bans.as_str(), params.iter().map(|param| param.as_ref() as &dyn ToSql))?;
+        emit(BAN_CSV_HEADER.to_string())?;
+        let mut exported = 0;
+        while let Some(row) = rows.next()? {
+            emit(ban_csv_row(&Ban::from_row(&row, now)))?;
+            exported += 1;
+        }
+        log_if_slow("export_bans_csv_query", start.elapsed());
+        Ok(exported)
+    }
+
+    // ORDER BY random() reads and sorts the whole banlist, but unlike TABLESAMPLE it always returns
+    // n rows when there are that many and never picks soft-deleted ones, which matters more for spot checks
+    pub fn sample_bans(&mut self, n: i64) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let sample_bans = format!("
+            SELECT * FROM {}
+            WHERE deleted_at IS NULL
+            ORDER BY random()
+            LIMIT $1;", bans_with_reasons());
+        debug!(utils::LOGGER, "Sampling bans"; "n" => n, "query" => log_query(&sample_bans));
+        let result: Vec<Row> = self.query("sample_bans", &sample_bans, &[&n])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn sync_snapshot(&mut self) -> Result<Snapshot, postgres::Error> {
+        let now = self.now();
+        let get_all_bans = get_bans_query(BanFields::Full);
+        debug!(utils::LOGGER, "Taking banlist snapshot"; "query" => log_query(&get_all_bans));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        // The bans and the cursor have to come from the same snapshot
+        transaction.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")?;
+        let server_time: NaiveDateTime = transaction.query_one("SELECT now()::timestamp;", &[])?.get(0);
+        let bans = transaction.query(get_all_bans.as_str(), &[])?
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect();
+        transaction.commit()?;
+        log_if_slow("sync_snapshot", start.elapsed());
+        Ok(Snapshot { bans, server_time })
+    }
+
+    pub fn ban_diff_since(&mut self, since: NaiveDateTime) -> Result<BanDiff, postgres::Error> {
+        let now = self.now();
+        let ban_diff = format!("
+            SELECT *, CASE
+                WHEN deleted_at IS NOT NULL THEN 'removed'
+                WHEN created_at > $1 THEN 'added'
+                ELSE 'changed'
+            END AS change
+            FROM {}
+            WHERE updated_at > $1
+            ORDER BY id;", bans_with_reasons());
+        debug!(utils::LOGGER, "Getting banlist diff";
+            "since" => since.timestamp(), "query" => log_query(&ban_diff));
+        let result: Vec<Row> = self.query("ban_diff_since", &ban_diff, &[&since])?;
+        let mut diff = BanDiff::default();
+        for row in result.iter() {
+            match row.get::<_, &str>("change") {
+                "removed" => diff.removed.push(row.get("id")),
+                "added" => diff.added.push(Ban::from_row(row, now)),
+                _ => diff.changed.push(Ban::from_row(row, now)),
+            }
+        }
+        Ok(diff)
+    }
+
+    pub fn top_reasons_in_window(&mut self, window: Duration, limit: i64) -> Result<Vec<(String, i64)>, postgres::Error> {
+        let now = self.now();
+        let top_reasons = format!("
+            SELECT lower(regexp_replace(btrim(reason), '\\s+', ' ', 'g')) AS normalized, COUNT(*)
+            FROM {}
+            WHERE deleted_at IS NULL AND reason IS NOT NULL
+            AND date > $3::timestamptz - $1::float8 * interval '1 second'
+            GROUP BY normalized
+            ORDER BY COUNT(*) DESC, normalized
+            LIMIT $2;", bans_with_reasons());
+        debug!(utils::LOGGER, "Getting top reasons";
+            "window" => window.as_secs(), "limit" => limit, "query" => log_query(&top_reasons));
+        let result: Vec<Row> = self.query("top_reasons_in_window", &top_reasons, &[&window.as_secs_f64(), &limit, &now])?;
+        Ok(result
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    // Seconds in which more than `threshold` bans were written, organic bans rarely share one
+    pub fn find_burst_imports(&mut self, threshold: i64) -> Result<Vec<(DateTime<Utc>, i64)>, postgres::Error> {
+        let find_bursts = format!("
+            SELECT date_trunc('second', date) AS second, COUNT(*)
+            FROM {}
+            WHERE deleted_at IS NULL
+            GROUP BY second
+            HAVING COUNT(*) > $1
+            ORDER BY second;", table("banlist"));
+        debug!(utils::LOGGER, "Finding burst imports";
+            "threshold" => threshold, "query" => log_query(&find_bursts));
+        let result: Vec<Row> = self.query("find_burst_imports", &find_bursts, &[&threshold])?;
+        Ok(result
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect())
+    }
+
+    pub fn count_bans_with_reason(&mut self, reason: &str) -> Result<i64, postgres::Error> {
+        let count_bans = format!("SELECT COUNT(*) FROM {} WHERE reason = $1 AND deleted_at IS NULL;", bans_with_reasons());
+        debug!(utils::LOGGER, "Counting bans with reason";
+            "reason" => reason, "query" => log_query(&count_bans));
+        let result: Vec<Row> = self.query("count_bans_with_reason", &count_bans, &[&reason])?;
+        Ok(match result.first() {
+            Some(row) => row.get(0),
+            None => 0,
+        })
+    }
+
+    // Bans matching any of the words, the ones whose reasons match the most of them first
+    pub fn search_bans_ranked(&mut self, terms: &str, limit: i64) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let search_bans = format!("
+            SELECT bans.* FROM {}, to_tsquery('simple', $1) q
+            WHERE deleted_at IS NULL AND reason_tsv @@ q
+            ORDER BY ts_rank(reason_tsv, q) DESC, id
+            LIMIT $2;", bans_with_reasons());
+        debug!(utils::LOGGER, "Searching bans";
+            "terms" => terms, "limit" => limit, "query" => log_query(&search_bans));
+        let result: Vec<Row> = self.query("search_bans_ranked", &search_bans, &[&terms, &limit])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn suggest_reasons(&mut self, input: &str, limit: i64) -> Result<Vec<String>, postgres::Error> {
+        let suggest_reasons = format!("
+            SELECT text FROM {} r
+            WHERE text % $1
+            AND EXISTS (SELECT 1 FROM {} b WHERE b.reason_id = r.id AND deleted_at IS NULL)
+            ORDER BY similarity(text, $1) DESC, text
+            LIMIT $2;", table("reasons"), table("banlist"));
+        debug!(utils::LOGGER, "Suggesting reasons";
+            "input" => input, "limit" => limit, "query" => log_query(&suggest_reasons));
+        let result: Vec<Row> = self.query("suggest_reasons", &suggest_reasons, &[&input, &limit])?;
+        Ok(result
+            .iter()
+            .map(|row| row.get(0))
             .collect())
     }
 
+    // Sorted ascending, clients can keep the list and binary search it
     pub fn get_banned_ids(&mut self) -> Result<Vec<i64>, postgres::Error> {
-        let get_all_bans = "SELECT id FROM banlist;";
-        debug!(utils::LOGGER, "Getting all bans as ids"; "query" => get_all_bans);
-        let result: Vec<Row> = self.conn.query(get_all_bans, &[])?;
+        let get_all_bans = QueryOp::BannedIds.sql();
+        debug!(utils::LOGGER, "Getting all bans as ids"; "query" => log_query(&get_all_bans));
+        let result: Vec<Row> = self.query("get_banned_ids", &get_all_bans, &[])?;
+        Ok(result
+            .into_iter()
+            .map(|row| row.get(0))
+            .collect())
+    }
+
+    pub fn find_invalid_ban_ids(&mut self) -> Result<Vec<i64>, postgres::Error> {
+        let find_invalid = format!("
+            SELECT id FROM {}
+            WHERE id <= 0
+            ORDER BY id;", table("banlist"));
+        debug!(utils::LOGGER, "Finding invalid ban ids"; "query" => log_query(&find_invalid));
+        let result: Vec<Row> = self.query("find_invalid_ban_ids", &find_invalid, &[])?;
         Ok(result
             .into_iter()
             .map(|row| row.get(0))
@@ -224,9 +1744,9 @@ impl Database {
     }
 
     pub fn get_total_ban_count(&mut self) -> Result<i64, postgres::Error> {
-        let get_all_bans = "SELECT COUNT(*) FROM banlist;";
-        debug!(utils::LOGGER, "Getting all bans"; "query" => get_all_bans);
-        let result: Vec<Row> = self.conn.query(get_all_bans, &[])?;
+        let get_all_bans = QueryOp::TotalBanCount.sql();
+        debug!(utils::LOGGER, "Getting all bans"; "query" => log_query(&get_all_bans));
+        let result: Vec<Row> = self.query("get_total_ban_count", &get_all_bans, &[])?;
         let count = match result.get(0) {
             Some(row) => row.get(0),
             None => 0
@@ -234,52 +1754,527 @@ impl Database {
         Ok(count)
     }
 
-    pub fn add_ban(&mut self, user_id: i64, reason: &String, admin_token: i32, message: &Option<String>) -> Result<(), postgres::Error> {
-        let upsert_ban = "
-            INSERT INTO banlist
-            VALUES ($1, $2, now(), $3, $4)
-            ON CONFLICT (id) DO
-            UPDATE SET reason=excluded.reason, date=excluded.date, message=excluded.message;";
+    // In UTC, None if there are no bans
+    pub fn ban_date_range(&mut self) -> Result<Option<(NaiveDateTime, NaiveDateTime)>, postgres::Error> {
+        let ban_date_range = format!("
+            SELECT MIN(date) AT TIME ZONE 'UTC', MAX(date) AT TIME ZONE 'UTC' FROM {}
+            WHERE deleted_at IS NULL;", table("banlist"));
+        debug!(utils::LOGGER, "Getting ban date range"; "query" => log_query(&ban_date_range));
+        let result: Vec<Row> = self.query("ban_date_range", &ban_date_range, &[])?;
+        Ok(match result.first() {
+            Some(row) => row.get::<_, Option<NaiveDateTime>>(0).zip(row.get::<_, Option<NaiveDateTime>>(1)),
+            None => None,
+        })
+    }
+
+    pub fn banlist_etag(&mut self) -> Result<String, postgres::Error> {
+        let get_version = format!("
+            SELECT GREATEST((SELECT max(updated_at) FROM {0}), (SELECT max(date) FROM {1})),
+                (SELECT COUNT(*) FROM {0}) + (SELECT COUNT(*) FROM {1});",
+            table("banlist"), table("whitelist"));
+        debug!(utils::LOGGER, "Getting banlist version"; "query" => log_query(&get_version));
+        let result: Vec<Row> = self.query("banlist_etag", &get_version, &[])?;
+        Ok(match result.first() {
+            Some(row) => ban_etag(row.get(0), row.get(1)),
+            None => ban_etag(None, 0),
+        })
+    }
+
+    // Returns false if an existing ban was kept because of the conflict policy
+    pub fn add_ban(&mut self, ban: &PendingBan, policy: ConflictPolicy) -> Result<bool, UserError> {
+        ensure_enabled("add_ban")?;
+        validate_user_id(ban.id)?;
+        let ban = PendingBan { reason: sanitize_reason(&ban.reason), ..ban.clone() };
+        // The same ban was just written, so only the update policy would write it again
+        if is_duplicate_ban(&ban) {
+            return Ok(policy == ConflictPolicy::Update);
+        }
+        let upsert_ban = upsert_ban_query(policy);
         debug!(utils::LOGGER, "Upserting ban";
-            "id" => &user_id, "reason" => &reason, "query" => upsert_ban);
-        self.conn.query(upsert_ban, &[&user_id, &reason, &admin_token, &message])?;
-        Ok(())
+            "id" => &ban.id, "reason" => &ban.reason, "query" => log_query(&upsert_ban));
+
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        let reason_id = upsert_reasons(&mut transaction, std::slice::from_ref(&ban.reason))?[0];
+        let written = transaction.execute(upsert_ban.as_str(), &[&ban.id, &reason_id, &ban.admin_token, &ban.message,
+            &ban.tags, &ban.evidence, &ban.language])?;
+        evict_over_cap(&mut transaction, &[ban.id], ban.admin_token, settings::ENV.general.max_bans)?;
+        transaction.commit()?;
+        log_if_slow("add_ban", start.elapsed());
+        if written > 0 {
+            record_written_bans(&[ban]);
+        }
+        Ok(written > 0)
+    }
+
+    // Checks the whitelist and writes the ban in one transaction, returns false if the id is whitelisted.
+    // add_whitelist takes the same per-id lock, so a whitelisting can't slip in between check and write
+    pub fn ban_unless_whitelisted(&mut self, ban: &PendingBan) -> Result<bool, UserError> {
+        ensure_enabled("add_ban")?;
+        validate_user_id(ban.id)?;
+        let reason = sanitize_reason(&ban.reason);
+        let is_whitelisted = format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = $1);", table("whitelist"));
+        let upsert_ban = upsert_ban_query(ConflictPolicy::Update);
+        debug!(utils::LOGGER, "Banning unless whitelisted";
+            "id" => &ban.id, "reason" => &reason, "query" => log_query(&upsert_ban));
+
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        transaction.execute("SELECT pg_advisory_xact_lock($1);", &[&ban.id])?;
+        let whitelisted: bool = transaction.query_one(is_whitelisted.as_str(), &[&ban.id])?.get(0);
+        if !whitelisted {
+            let reason_id = upsert_reasons(&mut transaction, &[reason])?[0];
+            transaction.execute(upsert_ban.as_str(), &[&ban.id, &reason_id, &ban.admin_token, &ban.message,
+                &ban.tags, &ban.evidence, &ban.language])?;
+            evict_over_cap(&mut transaction, &[ban.id], ban.admin_token, settings::ENV.general.max_bans)?;
+        }
+        transaction.commit()?;
+        log_if_slow("ban_unless_whitelisted", start.elapsed());
+        Ok(!whitelisted)
+    }
+
+    pub fn add_ban_batch(&mut self, bans: &[PendingBan]) -> Result<u64, postgres::Error> {
+        Ok(self.write_ban_batch(bans)?.len() as u64)
+    }
+
+    pub fn upsert_bans_reporting(&mut self, bans: &[PendingBan]) -> Result<Vec<BanOutcome>, UserError> {
+        ensure_enabled("add_ban")?;
+        for ban in bans {
+            validate_user_id(ban.id)?;
+        }
+        Ok(self.write_ban_batch(bans)?)
+    }
+
+    // A freshly inserted row has no xmax yet, one that hit the conflict clause has the updating transaction's.
+    // Restoring a soft-deleted ban is an update of its row, so it's reported as Updated
+    fn write_ban_batch(&mut self, bans: &[PendingBan]) -> Result<Vec<BanOutcome>, postgres::Error> {
+        let ids: Vec<i64> = bans.iter().map(|ban| ban.id).collect();
+        let reasons: Vec<String> = bans.iter().map(|ban| sanitize_reason(&ban.reason)).collect();
+        let admins: Vec<i32> = bans.iter().map(|ban| ban.admin_token).collect();
+        let messages: Vec<Option<&str>> = bans.iter().map(|ban| ban.message.as_deref()).collect();
+        // Postgres can't unnest arrays of arrays into rows, so the tags and evidence travel as JSON
+        let tags: Vec<String> = bans.iter().map(|ban| json!(ban.tags).to_string()).collect();
+        let evidence: Vec<String> = bans.iter().map(|ban| json!(ban.evidence).to_string()).collect();
+        let languages: Vec<Option<&str>> = bans.iter().map(|ban| ban.language.as_deref()).collect();
+        let insert_batch = format!("
+            INSERT INTO {} (id, reason_id, date, admin_token, message, tags, evidence, language)
+            SELECT id, reason_id, now(), admin_token, message,
+                ARRAY(SELECT jsonb_array_elements_text(tags::jsonb)),
+                ARRAY(SELECT jsonb_array_elements_text(evidence::jsonb)),
+                language
+            FROM unnest($1::bigint[], $2::integer[], $3::integer[], $4::text[], $5::text[], $6::text[], $7::text[])
+                AS t (id, reason_id, admin_token, message, tags, evidence, language)
+            {}
+            RETURNING id, (xmax = 0) AS inserted;", table("banlist"), ConflictPolicy::Update.on_conflict(&table("banlist")));
+        debug!(utils::LOGGER, "Writing ban batch";
+            "count" => bans.len(), "query" => log_query(&insert_batch));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        let reason_ids = upsert_reasons(&mut transaction, &reasons)?;
+        let rows = transaction.query(insert_batch.as_str(), &[&ids, &reason_ids, &admins, &messages, &tags, &evidence,
+                                                               &languages])?;
+        if let Some(last) = bans.last() {
+            evict_over_cap(&mut transaction, &ids, last.admin_token, settings::ENV.general.max_bans)?;
+        }
+        transaction.commit()?;
+        log_if_slow("write_ban_batch", start.elapsed());
+        Ok(rows
+            .iter()
+            .map(|row| BanOutcome::new(row.get("id"), row.get("inserted")))
+            .collect())
+    }
+
+    // Returns false if there is no such ban, links that are already attached are kept once
+    pub fn add_evidence(&mut self, user_id: i64, link: &str) -> Result<bool, UserError> {
+        ensure_enabled("add_ban")?;
+        let add_evidence = format!("
+            UPDATE {}
+            SET evidence = CASE WHEN $2 = ANY(evidence) THEN evidence ELSE array_append(evidence, $2) END
+            WHERE id = $1 AND deleted_at IS NULL;", table("banlist"));
+        debug!(utils::LOGGER, "Adding evidence to ban";
+            "id" => user_id, "query" => log_query(&add_evidence));
+        Ok(self.execute("add_evidence", &add_evidence, &[&user_id, &link])? > 0)
+    }
+
+    pub fn import_bans_legacy(&mut self, data: &str, admin_token: i32) -> Result<ImportReport, UserError> {
+        ensure_enabled("add_ban")?;
+        let (bans, mut errors) = parse_legacy_bans(data);
+        let ids: Vec<i64> = bans.iter().map(|ban| ban.id).collect();
+        let get_whitelisted = format!("SELECT id FROM {} WHERE id = ANY($1);", table("whitelist"));
+        debug!(utils::LOGGER, "Importing legacy bans";
+            "count" => ids.len(), "errors" => errors.len(), "query" => log_query(&get_whitelisted));
+        let whitelisted: Vec<i64> = self.query("import_bans_legacy", &get_whitelisted, &[&ids])?
+            .iter()
+            .map(|row| row.get("id"))
+            .collect();
+        // Goes through the same batch write as POST /banlist, so the ban cap applies too
+        let bans: Vec<PendingBan> = reject_whitelisted(bans, &whitelisted, &mut errors)
+            .into_iter()
+            .map(|ban| PendingBan {
+                id: ban.id,
+                reason: ban.reason,
+                admin_token,
+                message: None,
+                tags: vec![],
+                evidence: vec![],
+                language: None,
+            })
+            .collect();
+        let imported = self.write_ban_batch(&bans)?.len() as u64;
+        Ok(ImportReport { imported, errors })
+    }
+
+    pub fn update_reason_for_ids(&mut self, ids: &[i64], reason: &str) -> Result<u64, UserError> {
+        ensure_enabled("add_ban")?;
+        let reason = sanitize_reason(reason);
+        let update_reason = format!("
+            UPDATE {}
+            SET reason_id = $2
+            WHERE id = ANY($1) AND deleted_at IS NULL;", table("banlist"));
+        debug!(utils::LOGGER, "Updating reason for bans";
+            "count" => ids.len(), "reason" => &reason, "query" => log_query(&update_reason));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        let reason_id = upsert_reasons(&mut transaction, &[reason])?[0];
+        let updated = transaction.execute(update_reason.as_str(), &[&ids, &reason_id])?;
+        transaction.commit()?;
+        log_if_slow("update_reason_for_ids", start.elapsed());
+        forget_written_bans(ids);
+        Ok(updated)
     }
 
     pub fn get_ban(&mut self, user_id: i64) -> Result<Option<Ban>, postgres::Error> {
-        let get_ban = "SELECT * FROM banlist WHERE id = $1;";
+        let now = self.now();
+        let get_ban = format!("
+            SELECT * FROM {0}
+            WHERE id = COALESCE((SELECT canonical_id FROM {2} WHERE alias_id = $1), $1)
+            AND deleted_at IS NULL
+            AND NOT EXISTS (SELECT 1 FROM {1} w WHERE w.id IN (bans.id, $1));",
+            bans_with_reasons(), table("whitelist"), table("user_aliases"));
         debug!(utils::LOGGER, "Getting token by id";
-            "id" => user_id, "query" => get_ban);
-        let row: Option<Row> = self.conn.query(get_ban, &[&user_id])?.pop();
+            "id" => user_id, "query" => log_query(&get_ban));
+        let row: Option<Row> = self.query("get_ban", &get_ban, &[&user_id])?.pop();
 
-        Ok(match row {
-            Some(ban) => Some(Ban {
-                id: ban.get(0),
-                reason: ban.get(1),
-                date: ban.get(2),
-                admin: ban.get(3),
-                message: ban.try_get(4).unwrap_or(None),
-            }),
-            None => None,
-        })
+        Ok(row.as_ref().map(|row| Ban::from_row(row, now)))
     }
 
-    pub fn delete_ban(&mut self, user_id: i64) -> Result<(), postgres::Error> {
-        let delete_ban = "DELETE FROM banlist WHERE id = $1;";
+    // Resolves aliases and the whitelist like get_ban, but only reads the admin token id
+    pub fn get_ban_admin(&mut self, user_id: i64) -> Result<Option<i32>, postgres::Error> {
+        let get_ban_admin = format!("
+            SELECT admin_token FROM {0} b
+            WHERE id = COALESCE((SELECT canonical_id FROM {2} WHERE alias_id = $1), $1)
+            AND deleted_at IS NULL
+            AND NOT EXISTS (SELECT 1 FROM {1} w WHERE w.id IN (b.id, $1));",
+            table("banlist"), table("whitelist"), table("user_aliases"));
+        debug!(utils::LOGGER, "Getting ban admin";
+            "id" => user_id, "query" => log_query(&get_ban_admin));
+        let row: Option<Row> = self.query("get_ban_admin", &get_ban_admin, &[&user_id])?.pop();
+
+        Ok(row.map(|row| row.get(0)))
+    }
+
+    pub fn delete_ban(&mut self, user_id: i64, admin_token: i32) -> Result<(), UserError> {
+        ensure_enabled("delete_ban")?;
+        let delete_ban = format!("
+            UPDATE {}
+            SET deleted_at = now(), deleted_by = $2, deleted_kind = 'Deleted'
+            WHERE id = $1 AND deleted_at IS NULL;", table("banlist"));
         debug!(utils::LOGGER, "Deleting ban";
-            "id" => user_id, "query" => delete_ban);
-        self.conn.query(delete_ban, &[&user_id])?.pop();
+            "id" => user_id, "query" => log_query(&delete_ban));
+        self.execute("delete_ban", &delete_ban, &[&user_id, &admin_token])?;
+        forget_written_bans(&[user_id]);
+
+        Ok(())
+    }
+
+    pub fn pin_ban(&mut self, user_id: i64) -> Result<bool, UserError> {
+        self.set_pinned(user_id, true)
+    }
+
+    pub fn unpin_ban(&mut self, user_id: i64) -> Result<bool, UserError> {
+        self.set_pinned(user_id, false)
+    }
+
+    fn set_pinned(&mut self, user_id: i64, pinned: bool) -> Result<bool, UserError> {
+        ensure_enabled("add_ban")?;
+        let set_pinned = format!("
+            UPDATE {}
+            SET pinned = $2
+            WHERE id = $1 AND deleted_at IS NULL;", table("banlist"));
+        debug!(utils::LOGGER, "Setting ban pinned";
+            "id" => user_id, "pinned" => pinned, "query" => log_query(&set_pinned));
+        Ok(self.execute("set_pinned", &set_pinned, &[&user_id, &pinned])? > 0)
+    }
+
+    pub fn set_appeal_status(&mut self, user_id: i64, status: AppealStatus, admin_token: i32) -> Result<(), UserError> {
+        ensure_enabled("set_appeal_status")?;
+        if status == AppealStatus::Approved {
+            ensure_enabled("delete_ban")?;
+        }
+        let set_appeal_status = format!("
+            UPDATE {}
+            SET appeal_status = $2
+            WHERE id = $1 AND deleted_at IS NULL;", table("banlist"));
+        // An approved appeal lifts the ban, so it's soft-deleted in the same statement
+        let approve_appeal = format!("
+            UPDATE {}
+            SET appeal_status = $2, deleted_at = now(), deleted_by = $3, deleted_kind = 'Appealed'
+            WHERE id = $1 AND deleted_at IS NULL;", table("banlist"));
+        debug!(utils::LOGGER, "Setting appeal status";
+            "id" => user_id, "status" => format!("{:?}", status), "query" => log_query(&set_appeal_status));
+        if status == AppealStatus::Approved {
+            self.execute("set_appeal_status", &approve_appeal, &[&user_id, &status, &admin_token])?;
+            forget_written_bans(&[user_id]);
+        } else {
+            self.execute("set_appeal_status", &set_appeal_status, &[&user_id, &status])?;
+        }
+        Ok(())
+    }
+
+    pub fn get_bans_by_appeal_status(&mut self, status: AppealStatus) -> Result<Vec<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_bans_by_appeal_status = format!("
+            SELECT * FROM {}
+            WHERE appeal_status = $1 AND deleted_at IS NULL;", bans_with_reasons());
+        debug!(utils::LOGGER, "Getting bans by appeal status";
+            "status" => format!("{:?}", status), "query" => log_query(&get_bans_by_appeal_status));
+        let result: Vec<Row> = self.query("get_bans_by_appeal_status", &get_bans_by_appeal_status, &[&status])?;
+        Ok(result
+            .iter()
+            .map(|row| Ban::from_row(row, now))
+            .collect())
+    }
+
+    pub fn undo_last_delete(&mut self, admin_token: i32) -> Result<Option<Ban>, UserError> {
+        ensure_enabled("add_ban")?;
+        let now = self.now();
+        // Only bans removed with delete_ban, evicted bans and approved appeals stay deleted
+        let undo_delete = format!("
+            UPDATE {0}
+            SET deleted_at = NULL, deleted_by = NULL, deleted_kind = NULL
+            WHERE id = (
+                SELECT id FROM {0}
+                WHERE deleted_by = $1 AND deleted_at IS NOT NULL AND deleted_kind = 'Deleted'
+                ORDER BY deleted_at DESC
+                LIMIT 1)
+            RETURNING *, (SELECT text FROM {1} WHERE id = reason_id) AS reason;", table("banlist"), table("reasons"));
+        debug!(utils::LOGGER, "Restoring last deleted ban";
+            "admin" => admin_token, "query" => log_query(&undo_delete));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        let row: Option<Row> = transaction.query(undo_delete.as_str(), &[&admin_token])?.pop();
+        if let Some(row) = &row {
+            evict_over_cap(&mut transaction, &[row.get("id")], admin_token, settings::ENV.general.max_bans)?;
+        }
+        transaction.commit()?;
+        log_if_slow("undo_last_delete", start.elapsed());
+
+        Ok(row.as_ref().map(|row| Ban::from_row(row, now)))
+    }
+
+    pub fn add_alias(&mut self, alias_id: i64, canonical_id: i64, admin_token: i32) -> Result<(), UserError> {
+        ensure_enabled("add_ban")?;
+        let add_alias = format!("
+            INSERT INTO {} (alias_id, canonical_id, admin_token)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (alias_id) DO
+            UPDATE SET canonical_id = excluded.canonical_id, admin_token = excluded.admin_token;", table("user_aliases"));
+        debug!(utils::LOGGER, "Adding alias";
+            "alias" => alias_id, "canonical" => canonical_id, "query" => log_query(&add_alias));
+        self.execute("add_alias", &add_alias, &[&alias_id, &canonical_id, &admin_token])?;
+        Ok(())
+    }
+    //endregion
+
+    //region Scoped bans
+    pub fn get_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<Option<Ban>, postgres::Error> {
+        let now = self.now();
+        let get_scoped_ban = format!("SELECT * FROM {} WHERE id = $1 AND scope_id = $2;", table("scoped_bans"));
+        debug!(utils::LOGGER, "Getting scoped ban";
+            "id" => user_id, "scope" => scope_id, "query" => log_query(&get_scoped_ban));
+        let row: Option<Row> = self.query("get_scoped_ban", &get_scoped_ban, &[&user_id, &scope_id])?.pop();
+
+        Ok(row.as_ref().map(|row| Ban::from_row(row, now)))
+    }
+
+    pub fn add_scoped_ban(&mut self, scope_id: i64, ban: &PendingBan) -> Result<(), UserError> {
+        ensure_enabled("add_ban")?;
+        validate_user_id(ban.id)?;
+        let reason = sanitize_reason(&ban.reason);
+        let add_scoped_ban = format!("
+            INSERT INTO {} (id, scope_id, reason, message, admin_token)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (id, scope_id) DO
+            UPDATE SET reason = excluded.reason, message = excluded.message, date = now(),
+                admin_token = excluded.admin_token;", table("scoped_bans"));
+        debug!(utils::LOGGER, "Adding scoped ban";
+            "id" => ban.id, "scope" => scope_id, "query" => log_query(&add_scoped_ban));
+        self.execute("add_scoped_ban", &add_scoped_ban, &[&ban.id, &scope_id, &reason, &ban.message, &ban.admin_token])?;
+        Ok(())
+    }
 
+    // Returns false if the user wasn't banned in that scope
+    pub fn delete_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<bool, UserError> {
+        ensure_enabled("delete_ban")?;
+        let delete_scoped_ban = format!("DELETE FROM {} WHERE id = $1 AND scope_id = $2;", table("scoped_bans"));
+        debug!(utils::LOGGER, "Deleting scoped ban";
+            "id" => user_id, "scope" => scope_id, "query" => log_query(&delete_scoped_ban));
+        Ok(self.execute("delete_scoped_ban", &delete_scoped_ban, &[&user_id, &scope_id])? > 0)
+    }
+    //endregion
+
+    //region Whitelist
+    pub fn is_whitelisted(&mut self, user_id: i64) -> Result<bool, postgres::Error> {
+        let is_whitelisted = format!("SELECT EXISTS (SELECT 1 FROM {} WHERE id = $1);", table("whitelist"));
+        debug!(utils::LOGGER, "Checking whitelist";
+            "id" => user_id, "query" => log_query(&is_whitelisted));
+        let rows = self.query("is_whitelisted", &is_whitelisted, &[&user_id])?;
+        Ok(rows[0].get(0))
+    }
+
+    pub fn add_whitelist(&mut self, user_id: i64, admin_token: i32) -> Result<(), UserError> {
+        ensure_enabled("add_whitelist")?;
+        let add_whitelist = format!("
+            INSERT INTO {} (id, admin_token)
+            VALUES ($1, $2)
+            ON CONFLICT (id) DO NOTHING;", table("whitelist"));
+        debug!(utils::LOGGER, "Adding to whitelist";
+            "id" => user_id, "query" => log_query(&add_whitelist));
+        // Same per-id lock as ban_unless_whitelisted
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        transaction.execute("SELECT pg_advisory_xact_lock($1);", &[&user_id])?;
+        transaction.execute(add_whitelist.as_str(), &[&user_id, &admin_token])?;
+        transaction.commit()?;
+        log_if_slow("add_whitelist", start.elapsed());
+        Ok(())
+    }
+
+    pub fn remove_whitelist(&mut self, user_id: i64) -> Result<bool, UserError> {
+        ensure_enabled("remove_whitelist")?;
+        let remove_whitelist = format!("DELETE FROM {} WHERE id = $1;", table("whitelist"));
+        debug!(utils::LOGGER, "Removing from whitelist";
+            "id" => user_id, "query" => log_query(&remove_whitelist));
+        Ok(self.execute("remove_whitelist", &remove_whitelist, &[&user_id])? > 0)
+    }
+    //endregion
+
+    //region Maintenance
+    pub fn self_check(&mut self) -> Result<Vec<ConsistencyWarning>, postgres::Error> {
+        let checks = [
+            ("future_dated_bans",
+             format!("SELECT COUNT(*) FROM {} WHERE date > now();", table("banlist"))),
+            ("empty_reasons",
+             format!("SELECT COUNT(*) FROM {} WHERE reason = '';", bans_with_reasons())),
+            ("deleted_without_timestamp",
+             format!("SELECT COUNT(*) FROM {} WHERE deleted_by IS NOT NULL AND deleted_at IS NULL;", table("banlist"))),
+            ("empty_tokens",
+             format!("SELECT COUNT(*) FROM {} WHERE token = '';", table("tokens"))),
+            ("orphaned_aliases",
+             format!("SELECT COUNT(*) FROM {0} a WHERE NOT EXISTS (SELECT 1 FROM {1} b WHERE b.id = a.canonical_id);",
+                     table("user_aliases"), table("banlist"))),
+            ("orphaned_reason_ids",
+             format!("SELECT COUNT(*) FROM {0} b WHERE b.reason_id IS NOT NULL
+                      AND NOT EXISTS (SELECT 1 FROM {1} r WHERE r.id = b.reason_id);",
+                     table("banlist"), table("reasons"))),
+        ];
+        let mut warnings: Vec<ConsistencyWarning> = Vec::new();
+        for (check, query) in checks.iter() {
+            debug!(utils::LOGGER, "Running consistency check";
+                "check" => check, "query" => log_query(query));
+            let count: i64 = match self.query("self_check", query, &[])?.first() {
+                Some(row) => row.get(0),
+                None => 0,
+            };
+            if count > 0 {
+                warnings.push(ConsistencyWarning { check, count });
+            }
+        }
+        Ok(warnings)
+    }
+
+    pub fn explain(&mut self, op: QueryOp) -> Result<String, postgres::Error> {
+        let explain = format!("EXPLAIN (ANALYZE, BUFFERS) {}", op.sql().trim().trim_end_matches(';'));
+        debug!(utils::LOGGER, "Explaining query";
+            "op" => format!("{:?}", op), "query" => log_query(&explain));
+        let result: Vec<Row> = self.query("explain", &explain, &[])?;
+        let plan: Vec<String> = result.iter().map(|row| row.get(0)).collect();
+        Ok(plan.join("\n"))
+    }
+
+    pub fn run_readonly_query(&mut self, sql: &str) -> Result<Vec<Value>, UserError> {
+        let query = readonly_query_wrapper(sql);
+        debug!(utils::LOGGER, "Running read-only query"; "query" => log_query(&query));
+        let start = Instant::now();
+        let mut transaction = self.conn.transaction()?;
+        // Writes are refused by the server, whatever the query looks like
+        transaction.batch_execute("SET TRANSACTION READ ONLY")?;
+        let result = transaction.query(query.as_str(), &[]);
+        log_if_slow("run_readonly_query", start.elapsed());
+        let rows = result.map_err(|e| match e.code() {
+            Some(_) => {
+                warn!(utils::LOGGER, "Read-only query rejected"; "error" => e.to_string());
+                UserError::BadRequest("query was rejected by the database")
+            }
+            None => UserError::from(e),
+        })?;
+        transaction.rollback()?;
+        let json: String = rows[0].get(0);
+        match serde_json::from_str(&json)? {
+            Value::Array(rows) => Ok(rows),
+            _ => Err(UserError::Internal),
+        }
+    }
+    //endregion
+
+    //region Usage
+    pub fn increment_usage(&mut self, token_id: i32) -> Result<(), postgres::Error> {
+        if read_only() {
+            return Ok(());
+        }
+        let increment_usage = format!("
+            INSERT INTO {0} (token, date, count)
+            VALUES ($1, current_date, 1)
+            ON CONFLICT (token, date) DO
+            UPDATE SET count = {0}.count + 1;", table("token_usage"));
+        debug!(utils::LOGGER, "Incrementing token usage";
+            "token" => token_id, "query" => log_query(&increment_usage));
+        self.execute("increment_usage", &increment_usage, &[&token_id])?;
         Ok(())
     }
+
+    pub fn get_usage(&mut self, token_id: i32, date: NaiveDate) -> Result<i64, postgres::Error> {
+        let get_usage = format!("SELECT count FROM {} WHERE token = $1 AND date = $2;", table("token_usage"));
+        debug!(utils::LOGGER, "Getting token usage";
+            "token" => token_id, "date" => date.to_string(), "query" => log_query(&get_usage));
+        let row: Option<Row> = self.query("get_usage", &get_usage, &[&token_id, &date])?.pop();
+
+        Ok(match row {
+            Some(row) => row.get(0),
+            None => 0,
+        })
+    }
+
+    pub fn usage_report(&mut self, from: NaiveDate, to: NaiveDate) -> Result<Vec<(i32, i64)>, postgres::Error> {
+        let usage_report = format!("
+            SELECT token, SUM(count)::BIGINT FROM {}
+            WHERE date BETWEEN $1 AND $2
+            GROUP BY token
+            ORDER BY token;", table("token_usage"));
+        debug!(utils::LOGGER, "Getting usage report";
+            "from" => from.to_string(), "to" => to.to_string(), "query" => log_query(&usage_report));
+        let rows = self.query("usage_report", &usage_report, &[&from, &to])?;
+
+        Ok(rows.iter().map(|row| (row.get(0), row.get(1))).collect())
+    }
     //endregion
 
     //region Antiflood
     pub fn get_antiflood(&mut self, token_id: i32) -> Result<Antiflood, postgres::Error> {
-        let get_ban = "SELECT (banlist_all) FROM antiflood WHERE token = $1;";
+        let get_ban = format!("SELECT (banlist_all) FROM {} WHERE token = $1;", table("antiflood"));
         debug!(utils::LOGGER, "Getting token antiflood settings";
-            "token" => token_id, "query" => get_ban);
-        let row: Option<Row> = self.conn.query(get_ban, &[&token_id])?.pop();
+            "token" => token_id, "query" => log_query(&get_ban));
+        let row: Option<Row> = self.query("get_antiflood", &get_ban, &[&token_id])?.pop();
 
         Ok(match row {
             Some(antiflood) => Antiflood {
@@ -290,14 +2285,17 @@ impl Database {
     }
 
     pub fn set_antiflood_banlist_all(&mut self, token_id: i32, time: NaiveDateTime) -> Result<(), postgres::Error> {
-        let upsert_antiflood = "
-            INSERT INTO antiflood (token, banlist_all)
+        if read_only() {
+            return Ok(());
+        }
+        let upsert_antiflood = format!("
+            INSERT INTO {} (token, banlist_all)
             VALUES ($1, $2)
             ON CONFLICT (token) DO
-            UPDATE SET banlist_all=EXCLUDED.banlist_all;";
+            UPDATE SET banlist_all=EXCLUDED.banlist_all;", table("antiflood"));
         debug!(utils::LOGGER, "Updating antiflood";
-            "token" => &token_id, "column" => "banlist_all", "query" => upsert_antiflood);
-        self.conn.query(upsert_antiflood, &[&token_id, &time])?;
+            "token" => &token_id, "column" => "banlist_all", "query" => log_query(&upsert_antiflood));
+        self.query("set_antiflood_banlist_all", &upsert_antiflood, &[&token_id, &time])?;
         Ok(())
     }
     //endregion
@@ -1,10 +1,13 @@
+use std::error;
 use std::fmt;
+use std::io;
 
-use actix_web::error;
+use actix_web::error::ResponseError;
 use actix_web::http::StatusCode;
 use actix_web::HttpResponse;
 use failure::Fail;
 use postgres;
+use postgres::error::SqlState;
 use serde_json::{json, Value};
 
 use crate::utils;
@@ -17,15 +20,27 @@ pub enum UserError {
     MethodNotAllowed,
     Unauthorized,
     Forbidden,
+    Conflict,
     TooManyRequests {
         until: i64,
     },
+    ServiceReadOnly,
+    // The database couldn't be reached
+    Unavailable,
+    // A query was cancelled, usually by statement_timeout
+    Timeout,
 }
 
 impl From<postgres::Error> for UserError {
     fn from(item: postgres::Error) -> Self {
         error!(utils::LOGGER, "{}", item);
-        UserError::Internal
+        if item.code() == Some(&SqlState::QUERY_CANCELED) {
+            UserError::Timeout
+        } else if matches!(error::Error::source(&item), Some(source) if source.is::<io::Error>()) {
+            UserError::Unavailable
+        } else {
+            UserError::Internal
+        }
     }
 }
 
@@ -42,7 +57,7 @@ impl fmt::Display for UserError {
     }
 }
 
-impl error::ResponseError for UserError {
+impl ResponseError for UserError {
     fn error_response(&self) -> HttpResponse {
         self.to_response()
     }
@@ -53,50 +68,38 @@ impl error::ResponseError for UserError {
 }
 
 impl UserError {
-    fn to_json(&self) -> Value {
+    // The one place a variant is tied to a status, the body and the response follow from it
+    pub fn status(&self) -> StatusCode {
         match *self {
-            UserError::Internal => json!({
-                "code": StatusCode::INTERNAL_SERVER_ERROR.as_u16(),
-                "error": StatusCode::INTERNAL_SERVER_ERROR.canonical_reason()
-            }),
-            UserError::NotFound => json!({
-                "code": StatusCode::NOT_FOUND.as_u16(),
-                "error": StatusCode::NOT_FOUND.canonical_reason()
-            }),
-            UserError::BadRequest(reason) => json!({
-                "code": StatusCode::BAD_REQUEST.as_u16(),
-                "error": StatusCode::BAD_REQUEST.canonical_reason(),
-                "reason": reason
-            }),
-            UserError::MethodNotAllowed => json!({
-                "code": StatusCode::METHOD_NOT_ALLOWED.as_u16(),
-                "error": StatusCode::METHOD_NOT_ALLOWED.canonical_reason()
-            }),
-            UserError::Unauthorized => json!({
-                "code": StatusCode::UNAUTHORIZED.as_u16(),
-                "error": StatusCode::UNAUTHORIZED.canonical_reason()
-            }),
-            UserError::Forbidden => json!({
-                "code": StatusCode::FORBIDDEN.as_u16(),
-                "error": StatusCode::FORBIDDEN.canonical_reason()
-            }),
-            UserError::TooManyRequests { until } => json!({
-                "code": StatusCode::TOO_MANY_REQUESTS.as_u16(),
-                "error": StatusCode::TOO_MANY_REQUESTS.canonical_reason(),
-                "until": until
-            }),
+            UserError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+            UserError::NotFound => StatusCode::NOT_FOUND,
+            UserError::BadRequest(_) => StatusCode::BAD_REQUEST,
+            UserError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            UserError::Unauthorized => StatusCode::UNAUTHORIZED,
+            UserError::Forbidden => StatusCode::FORBIDDEN,
+            UserError::Conflict => StatusCode::CONFLICT,
+            UserError::TooManyRequests { .. } => StatusCode::TOO_MANY_REQUESTS,
+            UserError::ServiceReadOnly | UserError::Unavailable => StatusCode::SERVICE_UNAVAILABLE,
+            UserError::Timeout => StatusCode::GATEWAY_TIMEOUT,
         }
     }
 
-    pub fn to_response(&self) -> HttpResponse {
+    pub fn to_json(&self) -> Value {
+        let status = self.status();
+        let mut body = json!({
+            "code": status.as_u16(),
+            "error": status.canonical_reason()
+        });
         match *self {
-            UserError::Internal => HttpResponse::InternalServerError().json(self.to_json()),
-            UserError::NotFound => HttpResponse::NotFound().json(self.to_json()),
-            UserError::BadRequest(_) => HttpResponse::BadRequest().json(self.to_json()),
-            UserError::MethodNotAllowed => HttpResponse::MethodNotAllowed().json(self.to_json()),
-            UserError::Unauthorized => HttpResponse::Unauthorized().json(self.to_json()),
-            UserError::Forbidden => HttpResponse::Forbidden().json(self.to_json()),
-            UserError::TooManyRequests { until: _ } => HttpResponse::TooManyRequests().json(self.to_json()),
+            UserError::BadRequest(reason) => body["reason"] = json!(reason),
+            UserError::TooManyRequests { until } => body["until"] = json!(until),
+            UserError::ServiceReadOnly => body["reason"] = json!("the API is read-only right now"),
+            _ => {}
         }
+        body
+    }
+
+    pub fn to_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status()).json(self.to_json())
     }
 }
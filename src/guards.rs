@@ -1,22 +1,123 @@
+use std::error::Error;
+use std::net::IpAddr;
+use std::str;
+
+use actix_web::HttpRequest;
+use bytes::BytesMut;
 use chrono::{Duration, NaiveDateTime, Utc};
-use postgres_types::{FromSql, ToSql};
-use serde::{Deserialize, Serialize};
+use postgres_types::{FromSql, IsNull, to_sql_checked, ToSql, Type};
+#[cfg(feature = "schema")]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
 
 use crate::database::{Antiflood, Database};
 use crate::database::Token;
 use crate::errors::UserError;
+use crate::settings;
+use crate::utils;
 
-#[derive(Debug, ToSql, FromSql, Serialize, Deserialize)]
-#[postgres(name = "permission")]
+// Ordered from least to most privileged
+#[derive(Debug, PartialEq, PartialOrd, Deserialize)]
+#[cfg_attr(feature = "schema", derive(JsonSchema))]
 pub enum Permission {
+    // A value added to the database enum that this version doesn't know yet, grants nothing
+    #[serde(skip_deserializing)]
+    #[cfg_attr(feature = "schema", schemars(skip))]
+    Unknown,
     // Can read from the API
+    #[serde(alias = "user")]
     User,
     // Can add IDs to the API
+    #[serde(alias = "admin")]
     Admin,
     // Can create/revoke tokens
+    #[serde(alias = "root")]
     Root,
 }
 
+impl Permission {
+    // The database always uses the capitalized names, only the JSON casing is configurable
+    pub fn wire_name(&self, lowercase: bool) -> &'static str {
+        match (self, lowercase) {
+            (Permission::Unknown, false) => "Unknown",
+            (Permission::Unknown, true) => "unknown",
+            (Permission::User, false) => "User",
+            (Permission::Admin, false) => "Admin",
+            (Permission::Root, false) => "Root",
+            (Permission::User, true) => "user",
+            (Permission::Admin, true) => "admin",
+            (Permission::Root, true) => "root",
+        }
+    }
+
+    fn level(&self) -> i32 {
+        match self {
+            Permission::Unknown => 0,
+            Permission::User => 1,
+            Permission::Admin => 2,
+            Permission::Root => 3,
+        }
+    }
+
+    // Levels missing to reach `target`, negative if this is already above it
+    pub fn distance_to(&self, target: &Permission) -> i32 {
+        target.level() - self.level()
+    }
+
+    pub fn from_db_name(name: &str) -> Permission {
+        match name {
+            "User" => Permission::User,
+            "Admin" => Permission::Admin,
+            "Root" => Permission::Root,
+            _ => {
+                warn!(utils::LOGGER, "Unknown permission in database"; "permission" => name);
+                Permission::Unknown
+            }
+        }
+    }
+}
+
+// Written by hand because the derived impls refuse the whole type once the database enum has a value they don't know
+impl<'a> FromSql<'a> for Permission {
+    fn from_sql(_: &Type, raw: &'a [u8]) -> Result<Permission, Box<dyn Error + Sync + Send>> {
+        Ok(Permission::from_db_name(str::from_utf8(raw)?))
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "permission"
+    }
+}
+
+impl ToSql for Permission {
+    fn to_sql(&self, _: &Type, out: &mut BytesMut) -> Result<IsNull, Box<dyn Error + Sync + Send>> {
+        if *self == Permission::Unknown {
+            return Err("can't write an unknown permission".into());
+        }
+        out.extend_from_slice(self.wire_name(false).as_bytes());
+        Ok(IsNull::No)
+    }
+
+    fn accepts(ty: &Type) -> bool {
+        ty.name() == "permission"
+    }
+
+    to_sql_checked!();
+}
+
+impl Serialize for Permission {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.wire_name(settings::ENV.general.lowercase_permissions))
+    }
+}
+
+
+pub fn require_permission(token: &Token, min: Permission) -> Result<(), UserError> {
+    if token.permission >= min {
+        Ok(())
+    } else {
+        Err(UserError::Forbidden)
+    }
+}
 
 pub struct TokenGuard {
     pub token: Token,
@@ -25,38 +126,21 @@ pub struct TokenGuard {
 }
 
 impl TokenGuard {
-    pub fn new(token_header: String) -> Result<TokenGuard, UserError> {
+    pub fn from_request(req: &HttpRequest) -> Result<TokenGuard, UserError> {
+        TokenGuard::new(utils::get_auth_token(req)?, req.peer_addr().map(|addr| addr.ip()))
+    }
+
+    pub fn new(token_header: String, ip: Option<IpAddr>) -> Result<TokenGuard, UserError> {
         let mut db = Database::new()?;
-        if !token_header.is_empty() {
-            let token = match db.get_token(token_header)? {
-                Some(token) => token,
-                None => return Err(UserError::Unauthorized),
-            };
-            let antiflood = db.get_antiflood(token.id)?;
-
-            if token.retired {
-                return Err(UserError::Unauthorized);
-            }
+        let token = db.authenticate(&token_header, ip)?;
+        db.increment_usage(token.id)?;
+        let antiflood = db.get_antiflood(token.id)?;
 
-            Ok(TokenGuard { token, db, antiflood })
-        } else {
-            return Err(UserError::Unauthorized);
-        }
+        Ok(TokenGuard { token, db, antiflood })
     }
 
     pub fn admin(&self) -> bool {
-        match self.token.permission {
-            Permission::Admin => true,
-            Permission::Root => true,
-            _ => false,
-        }
-    }
-
-    pub fn root(&self) -> bool {
-        match self.token.permission {
-            Permission::Root => true,
-            _ => false,
-        }
+        require_permission(&self.token, Permission::Admin).is_ok()
     }
 
     pub fn banlist_all(&mut self) -> Result<(), UserError> {
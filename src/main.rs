@@ -3,6 +3,7 @@ extern crate postgres;
 extern crate slog;
 
 use std::process::exit;
+use std::time::Duration;
 
 use actix_web::{App, HttpServer, web};
 
@@ -15,7 +16,11 @@ mod database;
 mod errors;
 mod guards;
 mod routes;
+mod scheduler;
 mod settings;
+mod store;
+mod token_generator;
+mod upstream;
 #[cfg(test)]
 mod tests;
 
@@ -27,12 +32,50 @@ fn setup_database() -> Result<i32, postgres::Error> {
             return Ok(1);
         }
     };
-    db.create_genesis_token()?;
+    if database::read_only() {
+        warn!(utils::LOGGER, "Starting read-only, skipping genesis and seed tokens");
+    } else {
+        db.create_genesis_token()?;
+        let seeded = db.seed_tokens()?;
+        if seeded > 0 {
+            info!(utils::LOGGER, "Seeded tokens from the config"; "count" => seeded);
+        }
+    }
+    for warning in db.self_check()? {
+        warn!(utils::LOGGER, "Consistency check failed";
+            "check" => warning.check, "count" => warning.count);
+    }
+    let invalid_ids = db.find_invalid_ban_ids()?;
+    if !invalid_ids.is_empty() {
+        warn!(utils::LOGGER, "Banlist contains invalid user ids";
+            "count" => invalid_ids.len(), "ids" => format!("{:?}", invalid_ids));
+    }
+    db.close();
     Ok(0)
 }
 
+fn flush_buffered_bans() {
+    let flushed = Database::new().and_then(|mut db| database::flush_ban_buffer(&mut db));
+    match flushed {
+        Ok(0) => {}
+        Ok(count) => debug!(utils::LOGGER, "Flushed buffered bans"; "count" => count),
+        Err(e) => error!(utils::LOGGER, "Could not flush buffered bans"; "error" => e.to_string()),
+    }
+}
+
 fn run() -> Result<i32, postgres::Error> {
     info!(utils::LOGGER, "Starting {}", env!("CARGO_PKG_NAME"); "version" => &env!("CARGO_PKG_VERSION"));
+    if let Err(e) = settings::ENV.validate() {
+        error!(utils::LOGGER, "{}", e);
+        return Ok(1);
+    }
+    let collision_probability = settings::ENV.token_collision_probability();
+    if collision_probability > settings::MAX_COLLISION_PROBABILITY {
+        warn!(utils::LOGGER, "Token size is too small for the expected amount of tokens";
+            "token_size" => settings::ENV.general.token_size,
+            "expected_tokens" => settings::ENV.general.expected_tokens,
+            "collision_probability" => collision_probability);
+    }
     if settings::ENV.general.masterid == 777000 {
         warn!(utils::LOGGER, "MasterID not set. Defaulting to Telegrams id (777000). To avoid this set `masterid` under the `general` section in the config.")
     }
@@ -41,10 +84,21 @@ fn run() -> Result<i32, postgres::Error> {
         "Master ID is {}",
         settings::ENV.general.masterid
     );
+    database::set_read_only(settings::ENV.general.read_only);
     let db_code = setup_database()?;
     if db_code > 0 {
         return Ok(db_code);
     }
+    let mut scheduler = scheduler::Scheduler::new();
+    if database::buffer_bans() {
+        scheduler.register("flush_ban_buffer", Duration::from_millis(settings::ENV.general.ban_batch_flush_ms), || {
+            if database::ban_buffer_due() && !database::read_only() {
+                flush_buffered_bans();
+            }
+            Ok(())
+        });
+    }
+    let scheduler = scheduler.start();
     let location = format!(
         "{}:{}",
         settings::ENV.server.host,
@@ -52,7 +106,8 @@ fn run() -> Result<i32, postgres::Error> {
     );
     info!(utils::LOGGER, "Starting Server on {}", location);
     HttpServer::new(|| {
-        App::new()
+        let app = App::new()
+            .wrap_fn(routes::root::read_only_gate)
             .default_service(web::route().to(|| UserError::NotFound.to_response()))
             .service(
                 web::resource("/")
@@ -61,39 +116,179 @@ fn run() -> Result<i32, postgres::Error> {
             )
             .service(web::resource("/version").route(web::get().to(routes::root::version)))
             .service(web::resource("/stats").route(web::get().to(routes::root::stats)))
+            .service(
+                web::resource("/explain/{op}")
+                    .route(web::get().to(routes::root::explain))
+            )
+            .service(
+                web::resource("/lockdown")
+                    .route(web::put().to(routes::root::put_lockdown))
+                    .route(web::delete().to(routes::root::delete_lockdown)),
+            )
+            .service(
+                web::resource("/query")
+                    .route(web::post().to(routes::root::query))
+            )
             .service(
                 web::resource("/tokens")
                     .route(web::get().to(routes::tokens::get_tokens))
                     .route(web::post().to(routes::tokens::post_tokens)),
             )
+            .service(
+                web::resource("/tokens/permissions")
+                    .route(web::get().to(routes::tokens::get_permission_summary))
+            )
+            .service(
+                web::resource("/tokens/usage")
+                    .route(web::get().to(routes::tokens::get_usage_report))
+            )
+            .service(
+                web::resource("/tokens/master")
+                    .route(web::put().to(routes::tokens::put_master))
+            )
             .service(
                 web::resource("/tokens/{id}")
                     .route(web::get().to(routes::tokens::get_token))
                     .route(web::delete().to(routes::tokens::delete_token)),
             )
+            .service(
+                web::resource("/tokens/{id}/usage")
+                    .route(web::get().to(routes::tokens::get_token_usage))
+            )
+            .service(
+                web::resource("/tokens/{id}/note")
+                    .route(web::put().to(routes::tokens::put_token_note))
+            )
+            .service(
+                web::resource("/tokens/{id}/rotate")
+                    .route(web::post().to(routes::tokens::rotate_token))
+            )
             .service(
                 web::resource("/tokens/userid/{uid}")
                     .route(web::get().to(routes::tokens::get_token_by_userid))
+                    .route(web::patch().to(routes::tokens::patch_tokens_by_userid))
             )
             .service(
                 web::resource("/banlist")
                     .route(web::get().to(routes::banlist::get_bans))
-                    .route(web::post().to(routes::banlist::post_bans)),
+                    .route(web::post().to(routes::banlist::post_bans))
+                    .route(web::patch().to(routes::banlist::patch_bans)),
             )
             .service(
                 web::resource("/banlist/all")
                     .route(web::get().to(routes::banlist::get_bans_id_list))
             )
+            .service(
+                web::resource("/banlist/appeals")
+                    .route(web::get().to(routes::banlist::get_appeals))
+            )
+            .service(
+                web::resource("/banlist/diff")
+                    .route(web::get().to(routes::banlist::get_diff))
+            )
+            .service(
+                web::resource("/banlist/export")
+                    .route(web::get().to(routes::banlist::get_export))
+            )
+            .service(
+                web::resource("/banlist/export/csv")
+                    .route(web::get().to(routes::banlist::get_export_csv))
+            )
+            .service(
+                web::resource("/banlist/import")
+                    .route(web::post().to(routes::banlist::import_bans))
+            )
+            .service(
+                web::resource("/banlist/search/ranked")
+                    .route(web::get().to(routes::banlist::get_ranked_search))
+            )
+            .service(
+                web::resource("/banlist/search")
+                    .route(web::get().to(routes::banlist::get_search))
+            )
+            .service(
+                web::resource("/banlist/sample")
+                    .route(web::get().to(routes::banlist::get_sample))
+            )
+            .service(
+                web::resource("/banlist/bursts")
+                    .route(web::get().to(routes::banlist::get_bursts))
+            )
+            .service(
+                web::resource("/banlist/reasons")
+                    .route(web::get().to(routes::banlist::get_top_reasons))
+            )
+            .service(
+                web::resource("/banlist/reasons/count")
+                    .route(web::get().to(routes::banlist::get_reason_count))
+            )
+            .service(
+                web::resource("/banlist/reasons/suggest")
+                    .route(web::get().to(routes::banlist::get_reason_suggestions))
+            )
+            .service(
+                web::resource("/banlist/snapshot")
+                    .route(web::get().to(routes::banlist::get_snapshot))
+            )
+            .service(
+                web::resource("/banlist/undo")
+                    .route(web::post().to(routes::banlist::undo_delete))
+            )
+            .service(
+                web::resource("/banlist/{id}/admin")
+                    .route(web::get().to(routes::banlist::get_ban_admin))
+            )
+            .service(
+                web::resource("/banlist/{id}/alias")
+                    .route(web::put().to(routes::banlist::put_alias))
+            )
+            .service(
+                web::resource("/banlist/{id}/evidence")
+                    .route(web::post().to(routes::banlist::post_evidence))
+            )
+            .service(
+                web::resource("/banlist/{id}/pin")
+                    .route(web::put().to(routes::banlist::put_pin))
+                    .route(web::delete().to(routes::banlist::delete_pin)),
+            )
+            .service(
+                web::resource("/banlist/{id}/appeal")
+                    .route(web::put().to(routes::banlist::put_appeal))
+            )
             .service(
                 web::resource("/banlist/{id}")
                     .route(web::get().to(routes::banlist::get_ban))
                     .route(web::delete().to(routes::banlist::delete_ban)),
             )
+            .service(
+                web::resource("/scopes/{scope}/banlist/{id}")
+                    .route(web::get().to(routes::scopes::get_scoped_ban))
+                    .route(web::put().to(routes::scopes::put_scoped_ban))
+                    .route(web::delete().to(routes::scopes::delete_scoped_ban)),
+            )
+            .service(
+                web::resource("/whitelist/{id}")
+                    .route(web::get().to(routes::whitelist::get_whitelist))
+                    .route(web::put().to(routes::whitelist::put_whitelist))
+                    .route(web::delete().to(routes::whitelist::delete_whitelist)),
+            );
+        #[cfg(feature = "schema")]
+        let app = app.service(web::resource("/schema").route(web::get().to(routes::root::schema)));
+        #[cfg(feature = "msgpack")]
+        let app = app
+            .service(web::resource("/banlist/export/msgpack")
+                .route(web::get().to(routes::banlist::get_export_msgpack)))
+            .service(web::resource("/banlist/import/msgpack")
+                .route(web::post().to(routes::banlist::import_bans_msgpack)));
+        app
     })
         .bind(location)
         .unwrap()
         .run()
         .unwrap();
+    scheduler.stop();
+    // Nothing that was accepted may be lost on shutdown
+    flush_buffered_bans();
     Ok(0)
 }
 
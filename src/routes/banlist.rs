@@ -1,104 +1,756 @@
-use actix_web::{HttpRequest, HttpResponse, Result, web};
+use std::thread;
+use std::time::Duration;
+
+use actix_web::{error, HttpRequest, HttpResponse, Result, web};
+use futures::{Sink, Stream};
+use futures::sync::mpsc;
 use serde::Deserialize;
-use serde_json::Value;
+use serde_json::{json, Value};
 
-use crate::database::Database;
+use crate::database::{self, AppealStatus, Ban, BanFields, BanOutcome, BanQuery, ConflictPolicy, Database, PendingBan};
 use crate::errors::UserError;
-use crate::guards::TokenGuard;
-use crate::utils;
+use crate::guards::{Permission, require_permission, TokenGuard};
+use crate::store::Store;
+use crate::upstream::{self, Upstream};
+use crate::utils::{self, ApiResponse};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateBan {
     id: i64,
     reason: String,
     message: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    evidence: Vec<String>,
+    language: Option<String>,
+}
+
+const DEFAULT_PAGE_SIZE: i64 = 1000;
+pub const MAX_REASON_LENGTH: usize = 1024;
+pub const MAX_EVIDENCE_LENGTH: usize = 2048;
+pub const MAX_SAMPLE_SIZE: i64 = 1000;
+
+#[derive(Debug, Deserialize)]
+pub struct PostBans {
+    #[serde(default)]
+    on_conflict: ConflictPolicy,
+    // Skips whitelisted ids instead of writing bans that would be hidden anyway
+    #[serde(default)]
+    unless_whitelisted: bool,
+    // Answers with whether each id was inserted or updated
+    #[serde(default)]
+    report: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetDiff {
+    // Seconds since the epoch, fractions are kept so snapshot cursors can be used as is
+    since: f64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetReasonSuggestions {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetRankedSearch {
+    q: String,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetReasonCount {
+    reason: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetExport {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetSearchPage {
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetSample {
+    n: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetBursts {
+    threshold: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetTopReasons {
+    // Window in seconds, defaults to a week
+    window: Option<u64>,
+    limit: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AddEvidence {
+    link: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdateReason {
+    ids: Vec<i64>,
+    reason: String,
+}
+
+pub fn validate_reason(reason: &str) -> Result<(), UserError> {
+    if reason.is_empty() {
+        Err(UserError::BadRequest("ban reason can not be empty"))
+    } else if reason.chars().count() > MAX_REASON_LENGTH {
+        Err(UserError::BadRequest("ban reason is too long"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn validate_evidence(link: &str) -> Result<(), UserError> {
+    if !(link.starts_with("https://") || link.starts_with("http://")) {
+        Err(UserError::BadRequest("evidence has to be a http(s) link"))
+    } else if link.len() > MAX_EVIDENCE_LENGTH {
+        Err(UserError::BadRequest("evidence link is too long"))
+    } else {
+        Ok(())
+    }
 }
 
-pub fn get_bans(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
-    if guard.root() {
-        let mut db = Database::new()?;
-        let bans = db.get_bans()?;
-        let nicer_bans: Vec<Value> = bans
-            .iter()
-            .map(|ban| ban.raw_json())
-            .collect();
-        let bans_json = serde_json::to_value(nicer_bans).map_err(|e| {
-            error!(utils::LOGGER, "{}", e);
-            UserError::Internal
-        })?;
-
-        Ok(HttpResponse::Ok().json(bans_json))
+// ISO 639-1 or 639-3 codes, any case
+pub fn validate_language(language: &str) -> Result<(), UserError> {
+    if (2..=3).contains(&language.len()) && language.chars().all(|c| c.is_ascii_alphabetic()) {
+        Ok(())
     } else {
-        Err(UserError::Forbidden)
+        Err(UserError::BadRequest("language has to be an ISO 639 code"))
+    }
+}
+
+fn validate_new_ban(ban: &CreateBan) -> Result<(), UserError> {
+    database::validate_user_id(ban.id)?;
+    // A reason made up of control characters only is empty once stored
+    validate_reason(&database::sanitize_reason(&ban.reason))?;
+    for link in &ban.evidence {
+        validate_evidence(link)?;
+    }
+    if let Some(language) = &ban.language {
+        validate_language(language)?;
+    }
+    Ok(())
+}
+
+// Sanitized here once, so the dedup window and the stored ban see the same reason
+fn pending_ban(ban: &CreateBan, admin_token: i32) -> PendingBan {
+    PendingBan {
+        id: ban.id,
+        reason: database::sanitize_reason(&ban.reason),
+        admin_token,
+        message: ban.message.clone(),
+        tags: ban.tags.clone(),
+        evidence: ban.evidence.clone(),
+        language: ban.language.as_deref().map(str::to_ascii_lowercase),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetBans {
+    #[serde(default)]
+    fields: BanFields,
+    after: Option<i64>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+    tag: Option<String>,
+    language: Option<String>,
+    exclude: Option<String>,
+    // Leaves out expired bans
+    #[serde(default)]
+    active: bool,
+}
+
+// Filters that only some of the get_bans paths support are refused instead of silently dropped
+pub fn validate_get_bans(query: &GetBans) -> Result<(), UserError> {
+    if matches!(query.limit, Some(limit) if limit <= 0) {
+        return Err(UserError::BadRequest("limit has to be greater than 0"));
+    }
+    if matches!(query.offset, Some(offset) if offset < 0) {
+        return Err(UserError::BadRequest("offset can not be negative"));
+    }
+    let filtered = query.tag.is_some() || query.language.is_some() || query.after.is_some() || query.active;
+    if query.offset.is_some() && query.exclude.is_none() && filtered {
+        return Err(UserError::BadRequest("offset can not be combined with tag, language, after or active"));
+    }
+    // The queries take one filter each, a second one would be dropped
+    let filters = [query.exclude.is_some(), query.tag.is_some(), query.language.is_some(), query.after.is_some(), query.active];
+    if filters.iter().filter(|set| **set).count() > 1 {
+        return Err(UserError::BadRequest("only one of exclude, tag, language, after and active can be used"));
     }
+    // Only the page, exclude and after queries are limited, tag and language win over after
+    let limited = query.exclude.is_some()
+        || (query.tag.is_none() && query.language.is_none() && (query.after.is_some() || query.offset.is_some()));
+    if query.limit.is_some() && !limited {
+        return Err(UserError::BadRequest("limit needs offset, after or exclude"));
+    }
+    Ok(())
+}
+
+pub fn get_bans(req: HttpRequest, query: web::Query<GetBans>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    validate_get_bans(&query)?;
+    let mut db = Database::new()?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    if let (Some(offset), None) = (query.offset, &query.exclude) {
+        let page = db.get_bans_page(query.fields, limit, offset)?;
+        return Ok(HttpResponse::Ok().json(serde_json::to_value(page)?));
+    }
+    let bans = match (&query.exclude, &query.tag, &query.language, query.after) {
+        (Some(exclude), _, _, _) => db.get_bans_excluding(query.fields, &utils::parse_id_list(exclude)?,
+                                                          limit, query.offset.unwrap_or(0))?,
+        (None, Some(tag), _, _) => db.get_bans_by_tag(query.fields, tag)?,
+        (None, None, Some(language), _) => find_bans_by_language(&mut db, query.fields, language)?,
+        (None, None, None, Some(after)) => db.get_bans_after(query.fields, after, limit)?,
+        (None, None, None, None) if query.active => db.get_active_bans(query.fields)?,
+        (None, None, None, None) => db.get_bans(query.fields)?,
+    };
+    Ok(ApiResponse::from_bans(&bans).respond())
+}
+
+pub fn find_bans_by_language<S: Store>(store: &mut S, fields: BanFields, language: &str) -> Result<Vec<Ban>, UserError> {
+    validate_language(language)?;
+    store.get_bans_by_language(fields, &language.to_ascii_lowercase())
+}
+
+pub fn add_bans<S: Store>(store: &mut S, bans: &[CreateBan], admin_token: i32,
+                          policy: ConflictPolicy) -> Result<(), UserError> {
+    for ban in bans {
+        validate_new_ban(ban)?;
+        let written = store.add_ban(&pending_ban(ban, admin_token), policy)?;
+        if !written && policy == ConflictPolicy::Error {
+            return Err(UserError::Conflict);
+        }
+    }
+    Ok(())
+}
+
+pub fn add_bans_reporting<S: Store>(store: &mut S, bans: &[CreateBan], admin_token: i32)
+                                    -> Result<Vec<BanOutcome>, UserError> {
+    for ban in bans {
+        validate_new_ban(ban)?;
+    }
+    // One upsert can't touch a row twice, so only the last ban per id is written and reported
+    let mut pending: Vec<PendingBan> = Vec::with_capacity(bans.len());
+    for ban in bans.iter().rev() {
+        if !pending.iter().any(|p| p.id == ban.id) {
+            pending.push(pending_ban(ban, admin_token));
+        }
+    }
+    pending.reverse();
+    store.upsert_bans_reporting(&pending)
+}
+
+// Validates everything before writing anything, returns the ids that were skipped for being whitelisted
+pub fn add_bans_unless_whitelisted<S: Store>(store: &mut S, bans: &[CreateBan], admin_token: i32)
+                                             -> Result<Vec<i64>, UserError> {
+    for ban in bans {
+        validate_new_ban(ban)?;
+    }
+    let mut skipped = Vec::new();
+    for ban in bans {
+        if !store.ban_unless_whitelisted(&pending_ban(ban, admin_token))? {
+            skipped.push(ban.id);
+        }
+    }
+    Ok(skipped)
+}
+
+// Validates everything before buffering anything, returns whether the buffer should be flushed
+pub fn buffer_new_bans(bans: &[CreateBan], admin_token: i32) -> Result<bool, UserError> {
+    database::ensure_enabled("add_ban")?;
+    for ban in bans {
+        validate_new_ban(ban)?;
+    }
+    let mut due = false;
+    for ban in bans {
+        let ban = pending_ban(ban, admin_token);
+        if database::is_duplicate_ban(&ban) {
+            continue;
+        }
+        due |= database::buffer_ban(ban);
+    }
+    Ok(due)
 }
 
 pub fn post_bans(
     req: HttpRequest,
+    query: web::Query<PostBans>,
     data: web::Json<Vec<CreateBan>>,
 ) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
-    if guard.admin() {
-        let mut db = Database::new()?;
-        for ban in data.iter() {
-            if !ban.reason.is_empty() {
-                db.add_ban(ban.id,
-                           &ban.reason,
-                           guard.token.id,
-                           &ban.message)?;
-            } else {
-                return Err(UserError::BadRequest("ban reason can not be empty"));
-            }
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    if query.report {
+        if query.on_conflict != ConflictPolicy::Update || query.unless_whitelisted {
+            return Err(UserError::BadRequest("report can only be combined with the update policy"));
         }
-        Ok(HttpResponse::NoContent().body(""))
-    } else {
-        Err(UserError::Forbidden)
+        let outcomes = add_bans_reporting(&mut Database::new()?, &data, guard.token.id)?;
+        return Ok(HttpResponse::Ok().json(outcomes));
+    }
+    if query.unless_whitelisted {
+        if query.on_conflict != ConflictPolicy::Update {
+            return Err(UserError::BadRequest("unless_whitelisted always updates existing bans"));
+        }
+        let skipped = add_bans_unless_whitelisted(&mut Database::new()?, &data, guard.token.id)?;
+        return Ok(HttpResponse::Ok().json(json!({ "skipped": skipped })));
+    }
+    // Buffered bans are always upserted, other policies need the result of the write
+    if database::buffer_bans() && query.on_conflict == ConflictPolicy::Update {
+        if buffer_new_bans(&data, guard.token.id)? {
+            database::flush_ban_buffer(&mut Database::new()?)?;
+        }
+        return Ok(HttpResponse::NoContent().body(""));
+    }
+    let mut db = Database::new()?;
+    add_bans(&mut db, &data, guard.token.id, query.on_conflict)?;
+    Ok(HttpResponse::NoContent().body(""))
+}
+
+pub fn patch_bans(
+    req: HttpRequest,
+    data: web::Json<UpdateReason>,
+) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    validate_reason(&database::sanitize_reason(&data.reason))?;
+    let mut db = Database::new()?;
+    let updated = db.update_reason_for_ids(&data.ids, &data.reason)?;
+    Ok(HttpResponse::Ok().json(json!({ "updated": updated })))
+}
+
+pub fn import_bans(req: HttpRequest, body: String) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let mut db = Database::new()?;
+    let report = db.import_bans_legacy(&body, guard.token.id)?;
+    Ok(HttpResponse::Ok().json(serde_json::to_value(report)?))
+}
+
+pub fn get_export(req: HttpRequest, query: web::Query<GetExport>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let limit = query.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = query.offset.unwrap_or(0);
+    if limit <= 0 {
+        return Err(UserError::BadRequest("limit has to be greater than 0"));
+    }
+    if offset < 0 {
+        return Err(UserError::BadRequest("offset can not be negative"));
+    }
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().json(db.get_bans_json(limit, offset)?))
+}
+
+pub fn validate_ban_query(query: &BanQuery) -> Result<(), UserError> {
+    if let (Some(from), Some(to)) = (query.from, query.to) {
+        if from > to {
+            return Err(UserError::BadRequest("from has to be before to"));
+        }
+    }
+    if let Some(language) = &query.language {
+        validate_language(language)?;
+    }
+    if matches!(&query.search, Some(search) if search.trim().is_empty()) {
+        return Err(UserError::BadRequest("search can not be empty"));
+    }
+    Ok(())
+}
+
+pub fn get_search(req: HttpRequest, query: web::Query<BanQuery>,
+                  page: web::Query<GetSearchPage>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let limit = page.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = page.offset.unwrap_or(0);
+    if limit <= 0 {
+        return Err(UserError::BadRequest("limit has to be greater than 0"));
+    }
+    if offset < 0 {
+        return Err(UserError::BadRequest("offset can not be negative"));
+    }
+    validate_ban_query(&query)?;
+    let mut db = Database::new()?;
+    let page = db.query_bans(&query, limit, offset)?;
+    Ok(HttpResponse::Ok().json(serde_json::to_value(page)?))
+}
+
+pub fn get_ranked_search(req: HttpRequest, query: web::Query<GetRankedSearch>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let terms = database::ranked_search_terms(&query.q)
+        .ok_or(UserError::BadRequest("q has to contain a word"))?;
+    let limit = query.limit.unwrap_or(50);
+    if limit <= 0 {
+        return Err(UserError::BadRequest("limit has to be greater than 0"));
+    }
+    let mut db = Database::new()?;
+    let bans = db.search_bans_ranked(&terms, limit)?;
+    Ok(ApiResponse::from_bans(&bans).respond())
+}
+
+// The header is sent before the query runs, so a failure halfway only shows up as a truncated file and in the log
+pub fn get_export_csv(req: HttpRequest, query: web::Query<BanQuery>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    validate_ban_query(&query)?;
+    let mut db = Database::new()?;
+    let query = query.into_inner();
+    let (sender, receiver) = mpsc::channel::<web::Bytes>(16);
+    thread::spawn(move || {
+        let mut sender = sender.wait();
+        let exported = db.export_bans_csv_query(&query, |chunk| {
+            // Fails once the client went away
+            sender.send(web::Bytes::from(chunk)).map_err(|_| UserError::Internal)
+        });
+        match exported {
+            Ok(count) => debug!(utils::LOGGER, "Exported bans as CSV"; "count" => count),
+            Err(err) => error!(utils::LOGGER, "CSV export stopped"; "error" => format!("{:?}", err)),
+        }
+    });
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv; charset=utf-8")
+        .streaming(receiver.map_err(|_| error::ErrorInternalServerError("export failed"))))
+}
+
+#[cfg(feature = "msgpack")]
+pub fn get_export_msgpack(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let bans = db.get_bans(BanFields::Full)?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/msgpack")
+        .body(database::export_bans_msgpack(&bans)?))
+}
+
+// Takes what get_export_msgpack produces. Bans are written as new ones by the importing token, dates start now
+#[cfg(feature = "msgpack")]
+pub fn import_bans_msgpack(req: HttpRequest, body: web::Bytes) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let mut bans = Vec::new();
+    for ban in database::import_bans_msgpack(&body)? {
+        bans.push(CreateBan {
+            id: ban.id,
+            reason: ban.reason.ok_or(UserError::BadRequest("every ban needs a reason"))?,
+            message: ban.message,
+            tags: ban.tags,
+            evidence: ban.evidence,
+            language: ban.language,
+        });
+    }
+    add_bans(&mut Database::new()?, &bans, guard.token.id, ConflictPolicy::Update)?;
+    Ok(HttpResponse::Ok().json(json!({ "imported": bans.len() })))
+}
+
+pub fn get_snapshot(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let snapshot = db.sync_snapshot()?;
+    Ok(HttpResponse::Ok().json(serde_json::to_value(snapshot)?))
+}
+
+pub fn get_diff(req: HttpRequest, query: web::Query<GetDiff>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let since = utils::timestamp_from_secs_f64(query.since)?;
+    let mut db = Database::new()?;
+    let diff = db.ban_diff_since(since)?;
+    Ok(HttpResponse::Ok().json(serde_json::to_value(diff)?))
+}
+
+pub fn get_top_reasons(req: HttpRequest, query: web::Query<GetTopReasons>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let window = Duration::from_secs(query.window.unwrap_or(7 * 24 * 60 * 60));
+    let limit = query.limit.unwrap_or(10);
+    if limit <= 0 {
+        return Err(UserError::BadRequest("limit has to be greater than 0"));
+    }
+    let mut db = Database::new()?;
+    let reasons: Vec<Value> = db.top_reasons_in_window(window, limit)?
+        .iter()
+        .map(|(reason, count)| json!({
+            "reason": reason,
+            "count": count
+        }))
+        .collect();
+    Ok(ApiResponse::new(reasons).respond())
+}
+
+pub fn get_sample(req: HttpRequest, query: web::Query<GetSample>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let bans = take_sample(&mut db, query.n.unwrap_or(10))?;
+    Ok(ApiResponse::from_bans(&bans).respond())
+}
+
+pub fn get_bursts(req: HttpRequest, query: web::Query<GetBursts>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let threshold = query.threshold.unwrap_or(10);
+    if threshold <= 0 {
+        return Err(UserError::BadRequest("threshold has to be greater than 0"));
+    }
+    let mut db = Database::new()?;
+    let bursts: Vec<Value> = db.find_burst_imports(threshold)?
+        .iter()
+        .map(|(second, count)| json!({
+            "second": second.timestamp(),
+            "count": count
+        }))
+        .collect();
+    Ok(ApiResponse::new(bursts).respond())
+}
+
+pub fn get_reason_count(req: HttpRequest, query: web::Query<GetReasonCount>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    // Stored reasons are sanitized, so the lookup has to be as well
+    let reason = database::sanitize_reason(&query.reason);
+    validate_reason(&reason)?;
+    let mut db = Database::new()?;
+    let count = db.count_bans_with_reason(&reason)?;
+    Ok(HttpResponse::Ok().json(json!({
+        "reason": reason,
+        "count": count
+    })))
+}
+
+pub fn get_reason_suggestions(req: HttpRequest, query: web::Query<GetReasonSuggestions>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    if query.q.trim().is_empty() {
+        return Err(UserError::BadRequest("q can not be empty"));
+    }
+    let limit = query.limit.unwrap_or(5);
+    if limit <= 0 {
+        return Err(UserError::BadRequest("limit has to be greater than 0"));
     }
+    let mut db = Database::new()?;
+    Ok(ApiResponse::new(db.suggest_reasons(query.q.trim(), limit)?).respond())
 }
 
 pub fn get_ban(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let _guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
+    let _guard = TokenGuard::from_request(&req)?;
     let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
         UserError::BadRequest("could not convert user id to integer")
     })?;
     let mut db = Database::new()?;
-    match db.get_ban(user_id)? {
-        Some(ban) => Ok(HttpResponse::Ok().json(ban.json()?)),
-        None => Err(UserError::NotFound),
+    let upstream = upstream::from_settings();
+    Ok(HttpResponse::Ok().json(find_ban_or_upstream(&mut db, upstream.as_ref(), user_id)?.json()?))
+}
+
+pub fn find_ban<S: Store>(store: &mut S, user_id: i64) -> Result<Ban, UserError> {
+    store.get_ban(user_id)?.ok_or(UserError::NotFound)
+}
+
+// A local whitelisting also overrides the upstream
+pub fn find_ban_or_upstream<S: Store, U: Upstream>(store: &mut S, upstream: Option<&U>, user_id: i64)
+                                                   -> Result<Ban, UserError> {
+    if let Some(ban) = store.get_ban(user_id)? {
+        return Ok(ban);
+    }
+    match upstream {
+        Some(upstream) if !store.is_whitelisted(user_id)? => upstream.get_ban(user_id).ok_or(UserError::NotFound),
+        _ => Err(UserError::NotFound),
+    }
+}
+
+pub fn find_ban_admin<S: Store>(store: &mut S, user_id: i64) -> Result<i32, UserError> {
+    store.get_ban_admin(user_id)?.ok_or(UserError::NotFound)
+}
+
+pub fn get_ban_admin(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().json(json!({
+        "id": user_id,
+        "admin": find_ban_admin(&mut db, user_id)?
+    })))
+}
+
+pub fn take_sample<S: Store>(store: &mut S, n: i64) -> Result<Vec<Ban>, UserError> {
+    if n <= 0 {
+        return Err(UserError::BadRequest("n has to be greater than 0"));
+    }
+    if n > MAX_SAMPLE_SIZE {
+        return Err(UserError::BadRequest("n is too large"));
+    }
+    store.sample_bans(n)
+}
+
+pub fn remove_ban<S: Store>(store: &mut S, user_id: i64, admin_token: i32) -> Result<(), UserError> {
+    let ban = find_ban(store, user_id)?;
+    store.delete_ban(ban.id, admin_token)
+}
+
+pub fn append_evidence<S: Store>(store: &mut S, user_id: i64, link: &str) -> Result<Ban, UserError> {
+    validate_evidence(link)?;
+    let ban = find_ban(store, user_id)?;
+    if !store.add_evidence(ban.id, link)? {
+        return Err(UserError::NotFound);
+    }
+    find_ban(store, ban.id)
+}
+
+pub fn post_evidence(
+    req: HttpRequest,
+    data: web::Json<AddEvidence>,
+) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    let mut db = Database::new()?;
+    let ban = append_evidence(&mut db, user_id, &data.link)?;
+    Ok(HttpResponse::Ok().json(ban.json()?))
+}
+
+fn set_pinned(req: HttpRequest, pinned: bool) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    let mut db = Database::new()?;
+    let ban = find_ban(&mut db, user_id)?;
+    let updated = if pinned { db.pin_ban(ban.id)? } else { db.unpin_ban(ban.id)? };
+    if updated {
+        Ok(HttpResponse::NoContent().body(""))
+    } else {
+        Err(UserError::NotFound)
     }
 }
 
+pub fn put_pin(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    set_pinned(req, true)
+}
+
+pub fn delete_pin(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    set_pinned(req, false)
+}
+
 pub fn delete_ban(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
-    if guard.admin() {
-        let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
-            UserError::BadRequest("could not convert id to integer")
-        })?;
-
-        let mut db = Database::new()?;
-
-        match db.get_ban(user_id)? {
-            Some(_) => {
-                db.delete_ban(user_id)?;
-                Ok(HttpResponse::NoContent().body(""))
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert id to integer")
+    })?;
+
+    let mut db = Database::new()?;
+    remove_ban(&mut db, user_id, guard.token.id)?;
+    Ok(HttpResponse::NoContent().body(""))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAppeal {
+    status: AppealStatus,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetAppeals {
+    status: AppealStatus,
+}
+
+pub fn put_appeal(
+    req: HttpRequest,
+    data: web::Json<SetAppeal>,
+) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    let mut db = Database::new()?;
+    match db.get_ban(user_id)? {
+        Some(ban) => {
+            if !ban.appeal_status.can_transition_to(data.status) {
+                return Err(UserError::BadRequest("invalid appeal status transition"));
             }
-            None => Err(UserError::NotFound),
+            db.set_appeal_status(ban.id, data.status, guard.token.id)?;
+            Ok(HttpResponse::NoContent().body(""))
         }
-    } else {
-        Err(UserError::Forbidden)
+        None => Err(UserError::NotFound),
+    }
+}
+
+pub fn get_appeals(req: HttpRequest, query: web::Query<GetAppeals>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let mut db = Database::new()?;
+    let bans = db.get_bans_by_appeal_status(query.status)?;
+    Ok(ApiResponse::from_bans(&bans).respond())
+}
+
+pub fn undo_delete(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let mut db = Database::new()?;
+    match db.undo_last_delete(guard.token.id)? {
+        Some(ban) => Ok(HttpResponse::Ok().json(ban.json()?)),
+        None => Err(UserError::NotFound),
     }
 }
 
 pub fn get_bans_id_list(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let mut guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
+    let mut guard = TokenGuard::from_request(&req)?;
     guard.banlist_all()?;
     let mut db = Database::new()?;
-    let bans = db.get_banned_ids()?;
-    let nicer_bans: Vec<&i64> = bans
-        .iter()
-        .collect();
-    let response: Vec<String> = nicer_bans.iter().map(|i| i.to_string()).collect();
+    let etag = db.banlist_etag()?;
+    if utils::if_none_match(&req, &etag) {
+        return Ok(HttpResponse::NotModified().header("etag", etag).finish());
+    }
+    Ok(HttpResponse::Ok().header("etag", etag).body(banned_ids_body(&mut db)?))
+}
+
+pub fn banned_ids_body<S: Store>(store: &mut S) -> Result<String, UserError> {
+    let bans = store.get_banned_ids()?;
+    let response: Vec<String> = bans.iter().map(|i| i.to_string()).collect();
+    Ok(response.join("\n"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetAlias {
+    canonical: i64,
+}
 
-    Ok(HttpResponse::Ok().body(response.join("\n")))
+pub fn put_alias(
+    req: HttpRequest,
+    data: web::Json<SetAlias>,
+) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let alias_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    if alias_id == data.canonical {
+        return Err(UserError::BadRequest("a user can not be an alias of itself"));
+    }
+    let mut db = Database::new()?;
+    db.add_alias(alias_id, data.canonical, guard.token.id)?;
+    Ok(HttpResponse::NoContent().body(""))
 }
@@ -1,3 +1,5 @@
 pub mod banlist;
 pub mod root;
+pub mod scopes;
 pub mod tokens;
+pub mod whitelist;
@@ -1,9 +1,15 @@
-use actix_web::{HttpRequest, HttpResponse};
-use serde_json::json;
+use actix_web::{Error, HttpRequest, HttpResponse, web};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use futures::Future;
+use futures::future::{self, Either};
+use serde_json::{json, Value};
 
 use crate::settings;
-use crate::database::Database;
+use crate::database::{self, Database, QueryOp};
 use crate::errors::UserError;
+use crate::guards::{Permission, require_permission, TokenGuard};
+use crate::store::Store;
+use crate::utils;
 
 fn safe_href(name: &str, url: &str) -> String {
     format!(r#"<a rel="noopener" target="_blank" href="{}" class="white-no-dec-link">{}</a>"#, url, name)
@@ -44,9 +50,68 @@ pub fn version() -> HttpResponse {
 
 pub fn stats(_req: HttpRequest) -> Result<HttpResponse, UserError> {
     let mut db = Database::new()?;
-    let total_ban_count = db.get_total_ban_count()?;
-    let stats = json!({
-        "total_ban_count": total_ban_count
-    });
-    Ok(HttpResponse::Ok().json(serde_json::to_value(stats)?))
+    Ok(HttpResponse::Ok().json(stats_json(&mut db)?))
+}
+
+pub fn stats_json<S: Store>(store: &mut S) -> Result<Value, UserError> {
+    let total_ban_count = store.get_total_ban_count()?;
+    let range = store.ban_date_range()?;
+    Ok(json!({
+        "total_ban_count": total_ban_count,
+        "oldest_ban": range.map(|(oldest, _)| oldest.timestamp()),
+        "newest_ban": range.map(|(_, newest)| newest.timestamp())
+    }))
+}
+
+pub fn explain(req: HttpRequest, op: web::Path<QueryOp>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().content_type("text/plain").body(db.explain(*op)?))
+}
+
+pub fn query(req: HttpRequest, body: String) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    if body.trim().is_empty() {
+        return Err(UserError::BadRequest("query can not be empty"));
+    }
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().json(db.run_readonly_query(&body)?))
+}
+
+// Refuses every request that could write while the API is read-only, the database checks again on every write
+pub fn read_only_gate<S>(req: ServiceRequest, srv: &mut S) -> impl Future<Item = ServiceResponse, Error = Error>
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> {
+    gate_writes(database::read_only(), req, srv)
+}
+
+pub fn gate_writes<S>(read_only: bool, req: ServiceRequest, srv: &mut S) -> impl Future<Item = ServiceResponse, Error = Error>
+    where S: Service<Request = ServiceRequest, Response = ServiceResponse, Error = Error> {
+    if read_only && utils::is_write_request(req.method(), req.path()) {
+        Either::A(future::ok(req.error_response(UserError::ServiceReadOnly)))
+    } else {
+        Either::B(srv.call(req))
+    }
+}
+
+pub fn put_lockdown(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    set_lockdown(req, true)
+}
+
+pub fn delete_lockdown(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    set_lockdown(req, false)
+}
+
+fn set_lockdown(req: HttpRequest, read_only: bool) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    database::set_read_only(read_only);
+    warn!(utils::LOGGER, "Changed read-only mode"; "read_only" => read_only, "token" => guard.token.id);
+    Ok(HttpResponse::Ok().json(json!({ "read_only": read_only })))
+}
+
+#[cfg(feature = "schema")]
+pub fn schema() -> HttpResponse {
+    HttpResponse::Ok().json(crate::database::json_schemas())
 }
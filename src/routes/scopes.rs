@@ -0,0 +1,99 @@
+use actix_web::{HttpRequest, HttpResponse, Result, web};
+use serde::Deserialize;
+
+use crate::database::{self, Ban, Database, PendingBan};
+use crate::errors::UserError;
+use crate::guards::{Permission, require_permission, TokenGuard};
+use crate::routes::banlist::validate_reason;
+use crate::settings;
+use crate::store::Store;
+
+#[derive(Debug, Deserialize)]
+pub struct CreateScopedBan {
+    reason: String,
+    message: Option<String>,
+}
+
+fn ids(req: &HttpRequest) -> Result<(i64, i64), UserError> {
+    let scope_id: i64 = req.match_info().get("scope").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert scope id to integer")
+    })?;
+    let user_id: i64 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    Ok((scope_id, user_id))
+}
+
+fn require_enabled() -> Result<(), UserError> {
+    if settings::ENV.general.scoped_bans {
+        Ok(())
+    } else {
+        Err(UserError::NotFound)
+    }
+}
+
+// 0 is left out so it can't be mistaken for "no scope"
+pub fn validate_scope_id(scope_id: i64) -> Result<(), UserError> {
+    if scope_id == 0 {
+        Err(UserError::BadRequest("scope id can not be 0"))
+    } else {
+        Ok(())
+    }
+}
+
+pub fn find_scoped_ban<S: Store>(store: &mut S, scope_id: i64, user_id: i64) -> Result<Ban, UserError> {
+    store.get_scoped_ban(scope_id, user_id)?.ok_or(UserError::NotFound)
+}
+
+pub fn add_scoped_ban<S: Store>(store: &mut S, scope_id: i64, user_id: i64, ban: &CreateScopedBan,
+                                admin_token: i32) -> Result<Ban, UserError> {
+    validate_scope_id(scope_id)?;
+    database::validate_user_id(user_id)?;
+    validate_reason(&database::sanitize_reason(&ban.reason))?;
+    store.add_scoped_ban(scope_id, &PendingBan {
+        id: user_id,
+        reason: ban.reason.clone(),
+        admin_token,
+        message: ban.message.clone(),
+        tags: vec![],
+        evidence: vec![],
+        language: None,
+    })?;
+    find_scoped_ban(store, scope_id, user_id)
+}
+
+pub fn remove_scoped_ban<S: Store>(store: &mut S, scope_id: i64, user_id: i64) -> Result<(), UserError> {
+    if store.delete_scoped_ban(scope_id, user_id)? {
+        Ok(())
+    } else {
+        Err(UserError::NotFound)
+    }
+}
+
+pub fn get_scoped_ban(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let _guard = TokenGuard::from_request(&req)?;
+    require_enabled()?;
+    let (scope_id, user_id) = ids(&req)?;
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().json(find_scoped_ban(&mut db, scope_id, user_id)?.json()?))
+}
+
+pub fn put_scoped_ban(req: HttpRequest, data: web::Json<CreateScopedBan>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    require_enabled()?;
+    let (scope_id, user_id) = ids(&req)?;
+    let mut db = Database::new()?;
+    let ban = add_scoped_ban(&mut db, scope_id, user_id, &data, guard.token.id)?;
+    Ok(HttpResponse::Ok().json(ban.json()?))
+}
+
+pub fn delete_scoped_ban(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    require_enabled()?;
+    let (scope_id, user_id) = ids(&req)?;
+    let mut db = Database::new()?;
+    remove_scoped_ban(&mut db, scope_id, user_id)?;
+    Ok(HttpResponse::NoContent().body(""))
+}
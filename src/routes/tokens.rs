@@ -1,113 +1,264 @@
 use actix_web::{HttpRequest, HttpResponse, Result, web};
+use chrono::{NaiveDate, Utc};
 use serde::Deserialize;
+use serde_json::json;
 
 use crate::database::Database;
 use crate::errors::UserError;
-use crate::guards::{Permission, TokenGuard};
-use crate::utils;
+use crate::guards::{Permission, require_permission, TokenGuard};
+use crate::utils::{self, ApiResponse};
 
 #[derive(Debug, Deserialize)]
 pub struct CreateToken {
     id: i64,
+    // general.default_permission if left out
+    permission: Option<Permission>,
+    note: Option<String>,
+}
+
+pub const MAX_NOTE_LENGTH: usize = 1024;
+
+#[derive(Debug, Deserialize)]
+pub struct SetTokenNote {
+    // null clears the note
+    note: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetUsage {
+    date: Option<NaiveDate>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct GetUsageReport {
+    from: NaiveDate,
+    to: NaiveDate,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ReassignMaster {
+    userid: i64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UpdatePermission {
     permission: Permission,
 }
 
-pub fn get_tokens(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
-    if guard.root() {
-        let mut db = Database::new()?;
-        let tokens = db.get_tokens()?;
-        let tokens_json = serde_json::to_value(tokens).map_err(|e| {
-            error!(utils::LOGGER, "{}", e);
-            UserError::Internal
-        })?;
+#[derive(Debug, Deserialize)]
+pub struct GetTokens {
+    ids: Option<String>,
+}
 
-        Ok(HttpResponse::Ok().json(tokens_json))
+pub fn validate_note(note: &str) -> Result<(), UserError> {
+    if note.chars().count() > MAX_NOTE_LENGTH {
+        Err(UserError::BadRequest("token note is too long"))
     } else {
-        Err(UserError::Forbidden)
+        Ok(())
     }
 }
 
+pub fn get_tokens(req: HttpRequest, query: web::Query<GetTokens>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let tokens = match &query.ids {
+        Some(ids) => db.get_tokens_by_ids(&utils::parse_id_list(ids)?)?,
+        None => db.get_tokens()?,
+    };
+    Ok(ApiResponse::new(tokens).respond())
+}
+
 pub fn post_tokens(
     req: HttpRequest,
     data: web::Json<CreateToken>,
 ) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
-    if guard.root() {
-        let mut db = Database::new()?;
-        let token = db.create_token(&data.permission, data.id)?;
-        match db.get_token(token)? {
-            Some(token) => Ok(HttpResponse::Created().json(token.json()?)),
-            None => Err(UserError::NotFound),
-        }
-    } else {
-        Err(UserError::Forbidden)
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    if let Some(note) = &data.note {
+        validate_note(note)?;
+    }
+    let mut db = Database::new()?;
+    let note = data.note.as_deref();
+    let token = match &data.permission {
+        Some(permission) => db.create_token(permission, data.id, note)?,
+        None => db.create_default_token(data.id, note)?,
+    };
+    match db.get_token(token)? {
+        Some(token) => Ok(HttpResponse::Created().json(token.json()?)),
+        None => Err(UserError::NotFound),
     }
 }
 
 pub fn get_token(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
+    let guard = TokenGuard::from_request(&req)?;
 
     let mut db = Database::new()?;
     let _id = req.match_info().get("id").unwrap();
     if _id == "self" {
         match db.get_token(utils::get_auth_token(&req)?)? {
-            Some(token) => Ok(HttpResponse::Ok().json(token.json()?)),
+            Some(token) => Ok(HttpResponse::Ok().json(token.holder_json()?)),
             None => Err(UserError::NotFound),
         }
     } else {
-        if guard.root() {
-            let token_id: i32 = _id.parse().map_err(|_| {
-                UserError::BadRequest("could not convert token id to integer")
-            })?;
-            match db.get_token_by_id(token_id)? {
-                Some(token) => Ok(HttpResponse::Ok().json(token.json()?)),
-                None => Err(UserError::NotFound),
-            }
-        } else {
-            Err(UserError::Forbidden)
+        require_permission(&guard.token, Permission::Root)?;
+        let token_id: i32 = _id.parse().map_err(|_| {
+            UserError::BadRequest("could not convert token id to integer")
+        })?;
+        match db.get_token_by_id(token_id)? {
+            Some(token) => Ok(HttpResponse::Ok().json(token.json()?)),
+            None => Err(UserError::NotFound),
         }
     }
 }
 
 pub fn get_token_by_userid(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
+    let guard = TokenGuard::from_request(&req)?;
 
     let mut db = Database::new()?;
     let uid = req.match_info().get("uid").unwrap();
 
-    if guard.root() {
-        let uid: i64 = uid.parse().map_err(|_| {
-            UserError::BadRequest("could not convert user id to integer")
-        })?;
-        let tokens = db.get_token_by_userid(uid)?;
-        let tokens_json = serde_json::to_value(tokens).map_err(|e| {
-            error!(utils::LOGGER, "{}", e);
-            UserError::Internal
-        })?;
+    require_permission(&guard.token, Permission::Root)?;
+    let uid: i64 = uid.parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    let tokens = db.get_token_by_userid(uid)?;
+    Ok(ApiResponse::new(tokens).respond())
+}
 
-        Ok(HttpResponse::Ok().json(tokens_json))
-    } else {
-        Err(UserError::Forbidden)
-    }
+pub fn patch_tokens_by_userid(
+    req: HttpRequest,
+    data: web::Json<UpdatePermission>,
+) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+
+    require_permission(&guard.token, Permission::Root)?;
+    let uid: i64 = req.match_info().get("uid").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })?;
+    let mut db = Database::new()?;
+    let updated = db.update_permissions_by_userid(uid, &data.permission)?;
+    Ok(HttpResponse::Ok().json(json!({ "updated": updated })))
 }
 
 pub fn delete_token(req: HttpRequest) -> Result<HttpResponse, UserError> {
-    let guard = TokenGuard::new(utils::get_auth_token(&req)?)?;
+    let guard = TokenGuard::from_request(&req)?;
 
-    if guard.root() {
-        let mut db = Database::new()?;
-        let token_id: i32 = req.match_info().get("id").unwrap().parse().map_err(|_| {
-            UserError::BadRequest("could not convert token id to integer")
-        })?;
-        match db.get_token_by_id(token_id)? {
-            Some(_token) => {
-                db.revoke_token_by_id(token_id)?;
-                Ok(HttpResponse::NoContent().body(""))
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let token_id: i32 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert token id to integer")
+    })?;
+    match db.get_token_by_id(token_id)? {
+        Some(_token) => {
+            if !db.revoke_token_by_id(token_id)? {
+                debug!(utils::LOGGER, "Token was already revoked"; "id" => token_id);
             }
-            None => Err(UserError::NotFound),
+            Ok(HttpResponse::NoContent().body(""))
         }
-    } else {
-        Err(UserError::Forbidden)
+        None => Err(UserError::NotFound),
+    }
+}
+
+pub fn rotate_token(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let token_id: i32 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert token id to integer")
+    })?;
+    match db.get_token_by_id(token_id)? {
+        Some(_token) => {
+            let token = db.rotate_token(token_id)?;
+            match db.get_token(token)? {
+                Some(token) => Ok(HttpResponse::Ok().json(token.json()?)),
+                None => Err(UserError::NotFound),
+            }
+        }
+        None => Err(UserError::NotFound),
     }
 }
+
+pub fn put_token_note(req: HttpRequest, data: web::Json<SetTokenNote>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+
+    require_permission(&guard.token, Permission::Root)?;
+    let token_id: i32 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert token id to integer")
+    })?;
+    if let Some(note) = &data.note {
+        validate_note(note)?;
+    }
+    let mut db = Database::new()?;
+    if !db.set_token_note(token_id, data.note.as_deref())? {
+        return Err(UserError::NotFound);
+    }
+    match db.get_token_by_id(token_id)? {
+        Some(token) => Ok(HttpResponse::Ok().json(token.json()?)),
+        None => Err(UserError::NotFound),
+    }
+}
+
+pub fn put_master(req: HttpRequest, data: web::Json<ReassignMaster>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    db.reassign_master(data.userid)?;
+    match db.get_token_by_id(1)? {
+        Some(token) => Ok(HttpResponse::Ok().json(token.json()?)),
+        None => Err(UserError::NotFound),
+    }
+}
+
+pub fn get_token_usage(req: HttpRequest, query: web::Query<GetUsage>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    let token_id: i32 = req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert token id to integer")
+    })?;
+    let date = query.date.unwrap_or_else(|| Utc::today().naive_utc());
+    match db.get_token_by_id(token_id)? {
+        Some(_token) => {
+            let count = db.get_usage(token_id, date)?;
+            Ok(HttpResponse::Ok().json(json!({
+                "token": token_id,
+                "date": date.to_string(),
+                "count": count
+            })))
+        }
+        None => Err(UserError::NotFound),
+    }
+}
+
+pub fn get_usage_report(req: HttpRequest, query: web::Query<GetUsageReport>) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+
+    require_permission(&guard.token, Permission::Root)?;
+    if query.from > query.to {
+        return Err(UserError::BadRequest("from has to be before to"));
+    }
+    let mut db = Database::new()?;
+    let report: Vec<serde_json::Value> = db.usage_report(query.from, query.to)?
+        .iter()
+        .map(|(token, count)| json!({
+            "token": token,
+            "count": count
+        }))
+        .collect();
+    Ok(HttpResponse::Ok().json(json!({
+        "from": query.from.to_string(),
+        "to": query.to.to_string(),
+        "tokens": report
+    })))
+}
+
+pub fn get_permission_summary(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Root)?;
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().json(db.permission_summary()?))
+}
@@ -0,0 +1,43 @@
+use actix_web::{HttpRequest, HttpResponse, Result};
+use serde_json::json;
+
+use crate::database::Database;
+use crate::errors::UserError;
+use crate::guards::{Permission, require_permission, TokenGuard};
+
+fn user_id(req: &HttpRequest) -> Result<i64, UserError> {
+    req.match_info().get("id").unwrap().parse().map_err(|_| {
+        UserError::BadRequest("could not convert user id to integer")
+    })
+}
+
+pub fn get_whitelist(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let _guard = TokenGuard::from_request(&req)?;
+    let user_id = user_id(&req)?;
+    let mut db = Database::new()?;
+    Ok(HttpResponse::Ok().json(json!({
+        "id": user_id,
+        "whitelisted": db.is_whitelisted(user_id)?
+    })))
+}
+
+pub fn put_whitelist(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let user_id = user_id(&req)?;
+    let mut db = Database::new()?;
+    db.add_whitelist(user_id, guard.token.id)?;
+    Ok(HttpResponse::NoContent().body(""))
+}
+
+pub fn delete_whitelist(req: HttpRequest) -> Result<HttpResponse, UserError> {
+    let guard = TokenGuard::from_request(&req)?;
+    require_permission(&guard.token, Permission::Admin)?;
+    let user_id = user_id(&req)?;
+    let mut db = Database::new()?;
+    if db.remove_whitelist(user_id)? {
+        Ok(HttpResponse::NoContent().body(""))
+    } else {
+        Err(UserError::NotFound)
+    }
+}
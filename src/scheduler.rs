@@ -0,0 +1,68 @@
+use std::error::Error;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::utils;
+
+type TaskFn = Box<dyn FnMut() -> Result<(), Box<dyn Error>> + Send>;
+
+struct Task {
+    name: &'static str,
+    interval: Duration,
+    next_run: Instant,
+    run: TaskFn,
+}
+
+// Runs registered tasks at their interval on a single background thread
+#[derive(Default)]
+pub struct Scheduler {
+    tasks: Vec<Task>,
+}
+
+pub struct SchedulerHandle {
+    stop: Sender<()>,
+    thread: JoinHandle<()>,
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler::default()
+    }
+
+    pub fn register<F>(&mut self, name: &'static str, interval: Duration, run: F)
+        where F: FnMut() -> Result<(), Box<dyn Error>> + Send + 'static {
+        self.tasks.push(Task { name, interval, next_run: Instant::now() + interval, run: Box::new(run) });
+    }
+
+    pub fn start(mut self) -> SchedulerHandle {
+        let (stop, stopped) = mpsc::channel();
+        let thread = thread::spawn(move || loop {
+            let now = Instant::now();
+            for task in self.tasks.iter_mut().filter(|task| task.next_run <= now) {
+                if let Err(e) = (task.run)() {
+                    error!(utils::LOGGER, "Scheduled task failed"; "task" => task.name, "error" => e.to_string());
+                }
+                task.next_run = Instant::now() + task.interval;
+            }
+            let wait = self.tasks
+                .iter()
+                .map(|task| task.next_run.saturating_duration_since(Instant::now()))
+                .min()
+                .unwrap_or_else(|| Duration::from_secs(60));
+            match stopped.recv_timeout(wait) {
+                Err(RecvTimeoutError::Timeout) => continue,
+                _ => break,
+            }
+        });
+        SchedulerHandle { stop, thread }
+    }
+}
+
+impl SchedulerHandle {
+    // Waits for a running task to finish before returning
+    pub fn stop(self) {
+        let _ = self.stop.send(());
+        let _ = self.thread.join();
+    }
+}
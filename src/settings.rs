@@ -1,3 +1,4 @@
+use std::fmt;
 use std::path::PathBuf;
 
 use config::{Config, ConfigError, Environment, File};
@@ -5,6 +6,8 @@ use dirs::home_dir;
 use lazy_static::lazy_static;
 use serde::{Deserialize, Serialize};
 
+use crate::database;
+use crate::guards::Permission;
 use crate::utils;
 
 lazy_static! {
@@ -25,15 +28,83 @@ pub struct General {
     pub masterid: i64,
     pub token_size: u8,
     pub staging: bool,
+    pub ban_dedup_seconds: u64,
+    pub expected_tokens: u64,
+    pub strict_token_entropy: bool,
+    pub max_bans: u64,
+    pub ban_batch_size: usize,
+    pub ban_batch_flush_ms: u64,
+    pub failed_auth_threshold: u64,
+    pub lowercase_permissions: bool,
+    pub token_prefix: String,
+    pub envelope_lists: bool,
+    // Kept as a string, serializing a Permission needs the settings that are being loaded
+    pub default_permission: String,
+    pub read_only: bool,
+    // Allow bans that only apply in one community, keyed on (user id, scope id)
+    pub scoped_bans: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct DatabaseCfg {
     pub host: String,
     pub port: u16,
     pub name: String,
     pub username: String,
     pub password: String,
+    // Only applied by the queries, the migrations still create unprefixed tables
+    pub table_prefix: String,
+    pub slow_query_ms: u64,
+    pub log_queries: bool,
+}
+
+// Written by hand so the password never ends up in logs
+impl fmt::Debug for DatabaseCfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatabaseCfg")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("name", &self.name)
+            .field("username", &self.username)
+            .field("password", &"****")
+            .field("table_prefix", &self.table_prefix)
+            .field("slow_query_ms", &self.slow_query_ms)
+            .field("log_queries", &self.log_queries)
+            .finish()
+    }
+}
+
+impl DatabaseCfg {
+    /// Returns the name of `table` with the configured prefix applied
+    pub fn table(&self, table: &str) -> String {
+        format!("{}{}", self.table_prefix, table)
+    }
+
+    /// Returns a connection string with the password replaced, safe for logging
+    pub fn masked_dsn(&self) -> String {
+        format!("postgres://{}:****@{}:{}/{}", self.username, self.host, self.port, self.name)
+    }
+}
+
+/// Parses a permission the way the API does, `Root` or `root`
+pub fn parse_permission(name: &str) -> Option<Permission> {
+    serde_json::from_value(serde_json::Value::String(name.to_string())).ok()
+}
+
+// Size of the default nanoid alphabet
+const TOKEN_ALPHABET_SIZE: f64 = 64.0;
+pub const MAX_COLLISION_PROBABILITY: f64 = 1e-9;
+
+/// Birthday bound for at least one collision among `expected_tokens` tokens of `token_size` characters
+pub fn collision_probability(token_size: u8, expected_tokens: u64) -> f64 {
+    let possible_tokens = TOKEN_ALPHABET_SIZE.powi(token_size as i32);
+    let n = expected_tokens as f64;
+    -(-n * (n - 1.0) / (2.0 * possible_tokens)).exp_m1()
+}
+
+/// Checks that `identifier` is safe to interpolate into SQL as part of a table name
+pub fn is_safe_identifier(identifier: &str) -> bool {
+    identifier.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -42,11 +113,80 @@ pub struct ServerCfg {
     pub port: u16,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct SeedToken {
+    pub userid: i64,
+    pub permission: Permission,
+    // A fixed token, generated if not set
+    pub token: Option<String>,
+}
+
+impl fmt::Debug for SeedToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SeedToken")
+            .field("userid", &self.userid)
+            .field("permission", &self.permission)
+            .field("token", &self.token.as_ref().map(|_| "****"))
+            .finish()
+    }
+}
+
+// Another SpamWatch instance asked for bans that aren't found locally
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct UpstreamCfg {
+    // Empty disables the fallback
+    pub url: String,
+    pub token: String,
+    pub cache_seconds: u64,
+}
+
+// Written by hand so the token never ends up in logs
+impl fmt::Debug for UpstreamCfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamCfg")
+            .field("url", &self.url)
+            .field("token", &"****")
+            .field("cache_seconds", &self.cache_seconds)
+            .finish()
+    }
+}
+
+// Added to generated tokens so they show their permission, e.g. `root_`. It follows general.token_prefix and is
+// covered by the same checksum, so `sw_` and `root_` give `sw_root_<random><checksum>`
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PermissionPrefixes {
+    pub user: String,
+    pub admin: String,
+    pub root: String,
+}
+
+impl PermissionPrefixes {
+    pub fn for_permission(&self, permission: &Permission) -> &str {
+        match permission {
+            Permission::Unknown => "",
+            Permission::User => &self.user,
+            Permission::Admin => &self.admin,
+            Permission::Root => &self.root,
+        }
+    }
+}
+
 #[derive(Debug, Serialize, Deserialize)]
 pub struct Settings {
     pub database: DatabaseCfg,
     pub server: ServerCfg,
     pub general: General,
+    #[serde(default)]
+    pub seed_tokens: Vec<SeedToken>,
+    // Database operations refused with a 403, e.g. create_token on public replicas
+    #[serde(default)]
+    pub disabled_operations: Vec<String>,
+    #[serde(default)]
+    pub permission_prefixes: PermissionPrefixes,
+    #[serde(default)]
+    pub upstream: UpstreamCfg,
 }
 
 impl Default for Settings {
@@ -58,6 +198,9 @@ impl Default for Settings {
                 name: "SpamWatchAPI".to_string(),
                 username: "SpamWatchAPI".to_string(),
                 password: String::default(),
+                table_prefix: String::default(),
+                slow_query_ms: 1000,
+                log_queries: false,
             },
             server: ServerCfg {
                 host: "127.0.0.1".to_string(),
@@ -67,7 +210,24 @@ impl Default for Settings {
                 masterid: 777000,
                 token_size: 64,
                 staging: false,
+                ban_dedup_seconds: 0,
+                expected_tokens: 100_000,
+                strict_token_entropy: false,
+                max_bans: 0,
+                ban_batch_size: 0,
+                ban_batch_flush_ms: 1000,
+                failed_auth_threshold: 20,
+                lowercase_permissions: false,
+                token_prefix: String::new(),
+                envelope_lists: false,
+                default_permission: "User".to_string(),
+                read_only: false,
+                scoped_bans: false,
             },
+            seed_tokens: Vec::new(),
+            disabled_operations: Vec::new(),
+            permission_prefixes: PermissionPrefixes::default(),
+            upstream: UpstreamCfg { url: String::new(), token: String::new(), cache_seconds: 300 },
         }
     }
 }
@@ -101,4 +261,72 @@ impl Settings {
 
         Ok(settings.try_into().unwrap())
     }
+
+    pub fn token_collision_probability(&self) -> f64 {
+        collision_probability(self.general.token_size, self.general.expected_tokens)
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems: Vec<String> = Vec::new();
+        if self.database.host.is_empty() {
+            problems.push("database.host can not be empty".to_string());
+        }
+        if self.database.port == 0 {
+            problems.push("database.port can not be 0".to_string());
+        }
+        if self.database.name.is_empty() {
+            problems.push("database.name can not be empty".to_string());
+        }
+        if self.database.username.is_empty() {
+            problems.push("database.username can not be empty".to_string());
+        }
+        if !is_safe_identifier(&self.database.table_prefix) {
+            problems.push(format!(
+                "database.table_prefix `{}` may only contain alphanumeric characters and underscores",
+                self.database.table_prefix
+            ));
+        }
+        if self.server.host.is_empty() {
+            problems.push("server.host can not be empty".to_string());
+        }
+        if self.server.port == 0 {
+            problems.push("server.port can not be 0".to_string());
+        }
+        if self.general.token_size == 0 {
+            problems.push("general.token_size can not be 0".to_string());
+        } else if self.general.strict_token_entropy && self.token_collision_probability() > MAX_COLLISION_PROBABILITY {
+            problems.push(format!(
+                "general.token_size {} is too small for {} expected tokens",
+                self.general.token_size, self.general.expected_tokens
+            ));
+        }
+        if parse_permission(&self.general.default_permission).is_none() {
+            problems.push(format!(
+                "general.default_permission `{}` is not a permission", self.general.default_permission
+            ));
+        }
+        for (name, prefix) in [("user", &self.permission_prefixes.user), ("admin", &self.permission_prefixes.admin),
+                               ("root", &self.permission_prefixes.root)] {
+            if !prefix.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-') {
+                problems.push(format!(
+                    "permission_prefixes.{} `{}` may only contain alphanumeric characters, `_` and `-`", name, prefix
+                ));
+            }
+        }
+        if !self.upstream.url.is_empty() && !self.upstream.url.starts_with("http://")
+            && !self.upstream.url.starts_with("https://") {
+            problems.push(format!("upstream.url `{}` has to be an http(s) URL", self.upstream.url));
+        }
+        for operation in &self.disabled_operations {
+            if !database::OPERATIONS.contains(&operation.as_str()) {
+                problems.push(format!("disabled_operations `{}` is not an operation", operation));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::Message(format!("Invalid configuration: {}", problems.join(", "))))
+        }
+    }
 }
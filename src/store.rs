@@ -0,0 +1,90 @@
+use chrono::NaiveDateTime;
+
+use crate::database::{Ban, BanFields, BanOutcome, ConflictPolicy, Database, PendingBan};
+use crate::errors::UserError;
+
+/// Banlist operations the route logic depends on, so it can run against something other than Postgres
+pub trait Store {
+    fn get_ban(&mut self, user_id: i64) -> Result<Option<Ban>, UserError>;
+    fn get_ban_admin(&mut self, user_id: i64) -> Result<Option<i32>, UserError>;
+    fn add_ban(&mut self, ban: &PendingBan, policy: ConflictPolicy) -> Result<bool, UserError>;
+    fn ban_unless_whitelisted(&mut self, ban: &PendingBan) -> Result<bool, UserError>;
+    fn upsert_bans_reporting(&mut self, bans: &[PendingBan]) -> Result<Vec<BanOutcome>, UserError>;
+    fn add_evidence(&mut self, user_id: i64, link: &str) -> Result<bool, UserError>;
+    fn get_bans_by_language(&mut self, fields: BanFields, language: &str) -> Result<Vec<Ban>, UserError>;
+    fn sample_bans(&mut self, n: i64) -> Result<Vec<Ban>, UserError>;
+    fn delete_ban(&mut self, user_id: i64, admin_token: i32) -> Result<(), UserError>;
+    fn get_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<Option<Ban>, UserError>;
+    fn add_scoped_ban(&mut self, scope_id: i64, ban: &PendingBan) -> Result<(), UserError>;
+    fn delete_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<bool, UserError>;
+    fn get_banned_ids(&mut self) -> Result<Vec<i64>, UserError>;
+    fn get_total_ban_count(&mut self) -> Result<i64, UserError>;
+    fn ban_date_range(&mut self) -> Result<Option<(NaiveDateTime, NaiveDateTime)>, UserError>;
+    fn is_whitelisted(&mut self, user_id: i64) -> Result<bool, UserError>;
+}
+
+impl Store for Database {
+    fn get_ban(&mut self, user_id: i64) -> Result<Option<Ban>, UserError> {
+        Ok(Database::get_ban(self, user_id)?)
+    }
+
+    fn get_ban_admin(&mut self, user_id: i64) -> Result<Option<i32>, UserError> {
+        Ok(Database::get_ban_admin(self, user_id)?)
+    }
+
+    fn add_ban(&mut self, ban: &PendingBan, policy: ConflictPolicy) -> Result<bool, UserError> {
+        Database::add_ban(self, ban, policy)
+    }
+
+    fn ban_unless_whitelisted(&mut self, ban: &PendingBan) -> Result<bool, UserError> {
+        Database::ban_unless_whitelisted(self, ban)
+    }
+
+    fn upsert_bans_reporting(&mut self, bans: &[PendingBan]) -> Result<Vec<BanOutcome>, UserError> {
+        Database::upsert_bans_reporting(self, bans)
+    }
+
+    fn add_evidence(&mut self, user_id: i64, link: &str) -> Result<bool, UserError> {
+        Database::add_evidence(self, user_id, link)
+    }
+
+    fn get_bans_by_language(&mut self, fields: BanFields, language: &str) -> Result<Vec<Ban>, UserError> {
+        Ok(Database::get_bans_by_language(self, fields, language)?)
+    }
+
+    fn sample_bans(&mut self, n: i64) -> Result<Vec<Ban>, UserError> {
+        Ok(Database::sample_bans(self, n)?)
+    }
+
+    fn delete_ban(&mut self, user_id: i64, admin_token: i32) -> Result<(), UserError> {
+        Database::delete_ban(self, user_id, admin_token)
+    }
+
+    fn get_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<Option<Ban>, UserError> {
+        Ok(Database::get_scoped_ban(self, scope_id, user_id)?)
+    }
+
+    fn add_scoped_ban(&mut self, scope_id: i64, ban: &PendingBan) -> Result<(), UserError> {
+        Database::add_scoped_ban(self, scope_id, ban)
+    }
+
+    fn delete_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<bool, UserError> {
+        Database::delete_scoped_ban(self, scope_id, user_id)
+    }
+
+    fn get_banned_ids(&mut self) -> Result<Vec<i64>, UserError> {
+        Ok(Database::get_banned_ids(self)?)
+    }
+
+    fn get_total_ban_count(&mut self) -> Result<i64, UserError> {
+        Ok(Database::get_total_ban_count(self)?)
+    }
+
+    fn ban_date_range(&mut self) -> Result<Option<(NaiveDateTime, NaiveDateTime)>, UserError> {
+        Ok(Database::ban_date_range(self)?)
+    }
+
+    fn is_whitelisted(&mut self, user_id: i64) -> Result<bool, UserError> {
+        Ok(Database::is_whitelisted(self, user_id)?)
+    }
+}
@@ -0,0 +1,288 @@
+#[cfg(test)]
+mod post {
+    use actix_service::Service;
+    use actix_web::{App, web};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::routes;
+
+    #[test]
+    fn test_undo_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/undo")
+                    .route(web::post().to(routes::banlist::undo_delete)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::post().uri("/banlist/undo").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_alias_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/{id}/alias")
+                    .route(web::put().to(routes::banlist::put_alias)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::put()
+            .uri("/banlist/777001/alias")
+            .set_json(&serde_json::json!({"canonical": 777000}))
+            .to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_pin_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/{id}/pin")
+                    .route(web::put().to(routes::banlist::put_pin))
+                    .route(web::delete().to(routes::banlist::delete_pin)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::put().uri("/banlist/777000/pin").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[cfg(test)]
+mod get {
+    use actix_service::Service;
+    use actix_web::{App, web};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::routes;
+
+    #[test]
+    fn test_top_reasons_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/reasons")
+                    .route(web::get().to(routes::banlist::get_top_reasons)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/reasons?window=86400").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_bursts_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/bursts")
+                    .route(web::get().to(routes::banlist::get_bursts)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/bursts?threshold=5").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_search_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/search")
+                    .route(web::get().to(routes::banlist::get_search)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/search?tag=crypto&search=airdrop&limit=10").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_sample_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/sample")
+                    .route(web::get().to(routes::banlist::get_sample)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/sample?n=5").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_export_csv_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/export/csv")
+                    .route(web::get().to(routes::banlist::get_export_csv)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/export/csv?tag=crypto").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_ban_admin_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/{id}/admin")
+                    .route(web::get().to(routes::banlist::get_ban_admin)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/777000/admin").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_reason_count_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/reasons/count")
+                    .route(web::get().to(routes::banlist::get_reason_count)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/reasons/count?reason=spam").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_reason_suggestions_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist/reasons/suggest")
+                    .route(web::get().to(routes::banlist::get_reason_suggestions)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist/reasons/suggest?q=spamm").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_bans_excluding_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/banlist")
+                    .route(web::get().to(routes::banlist::get_bans)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/banlist?exclude=777000,777001&limit=10").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[cfg(test)]
+mod reason {
+    use crate::routes::banlist::{MAX_REASON_LENGTH, validate_reason};
+
+    #[test]
+    fn test_valid_reason() {
+        assert!(validate_reason("spam").is_ok());
+        assert!(validate_reason(&"a".repeat(MAX_REASON_LENGTH)).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_reason() {
+        assert!(validate_reason("").is_err());
+        assert!(validate_reason(&"a".repeat(MAX_REASON_LENGTH + 1)).is_err());
+    }
+}
+
+#[cfg(test)]
+mod get_bans_query {
+    use crate::errors::UserError;
+    use crate::routes::banlist::{validate_get_bans, GetBans};
+
+    fn query(json: serde_json::Value) -> GetBans {
+        serde_json::from_value(json).unwrap()
+    }
+
+    fn assert_bad_request(json: serde_json::Value) {
+        match validate_get_bans(&query(json.clone())) {
+            Err(UserError::BadRequest(_)) => {}
+            other => panic!("expected a bad request for {}, got {:?}", json, other),
+        }
+    }
+
+    #[test]
+    fn test_supported_combinations() {
+        assert!(validate_get_bans(&query(serde_json::json!({}))).is_ok());
+        assert!(validate_get_bans(&query(serde_json::json!({"offset": 100, "limit": 10}))).is_ok());
+        assert!(validate_get_bans(&query(serde_json::json!({"exclude": "1,2", "offset": 100}))).is_ok());
+    }
+
+    #[test]
+    fn test_offset_with_filters() {
+        assert_bad_request(serde_json::json!({"tag": "crypto", "offset": 100}));
+        assert_bad_request(serde_json::json!({"language": "de", "offset": 0}));
+        assert_bad_request(serde_json::json!({"after": 5, "offset": 10}));
+        assert_bad_request(serde_json::json!({"active": true, "offset": 10}));
+    }
+
+    #[test]
+    fn test_active_with_other_filters() {
+        assert_bad_request(serde_json::json!({"tag": "crypto", "active": true}));
+        assert_bad_request(serde_json::json!({"after": 5, "active": true}));
+        assert_bad_request(serde_json::json!({"exclude": "1", "active": true}));
+        assert_bad_request(serde_json::json!({"tag": "crypto", "language": "de"}));
+        assert!(validate_get_bans(&query(serde_json::json!({"active": true}))).is_ok());
+    }
+
+    #[test]
+    fn test_limit_without_a_limited_query() {
+        assert_bad_request(serde_json::json!({"limit": 10}));
+        assert_bad_request(serde_json::json!({"tag": "crypto", "limit": 10}));
+        assert_bad_request(serde_json::json!({"active": true, "limit": 10}));
+        assert!(validate_get_bans(&query(serde_json::json!({"after": 5, "limit": 10}))).is_ok());
+        assert!(validate_get_bans(&query(serde_json::json!({"exclude": "1", "limit": 10}))).is_ok());
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        assert_bad_request(serde_json::json!({"limit": 0, "offset": 0}));
+        assert_bad_request(serde_json::json!({"offset": -1}));
+    }
+}
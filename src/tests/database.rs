@@ -0,0 +1,1003 @@
+use chrono::{TimeZone, Utc};
+
+use crate::database::{AppealStatus, Ban, PendingBan};
+
+// A ban with every field set, tests override the ones they're about with struct update syntax
+pub fn sample_ban() -> Ban {
+    Ban {
+        id: 777000,
+        reason: Some("spam".to_string()),
+        date: Utc.timestamp(0, 0),
+        admin: 1,
+        message: None,
+        appeal_status: AppealStatus::None,
+        tags: vec![],
+        evidence: vec![],
+        language: None,
+        expires_at: None,
+        pinned: false,
+        active: true,
+    }
+}
+
+pub fn sample_pending_ban() -> PendingBan {
+    PendingBan {
+        id: 777000,
+        reason: "spam".to_string(),
+        admin_token: 1,
+        message: None,
+        tags: vec![],
+        evidence: vec![],
+        language: None,
+    }
+}
+
+#[cfg(test)]
+mod ban_fields {
+    use chrono::{TimeZone, Utc};
+
+    use crate::database::{ban_csv_row, Ban};
+    #[cfg(feature = "msgpack")]
+    use crate::database::{export_bans_msgpack, import_bans_msgpack};
+
+    use super::sample_ban;
+
+    #[test]
+    fn test_light_ban_json() {
+        // A Light row has no reason column, so from_row leaves it at None
+        let ban = Ban { reason: None, ..sample_ban() };
+        let json = ban.json().unwrap();
+        assert!(json.get("reason").is_none());
+        assert_eq!(json["id"], 777000);
+    }
+
+    #[test]
+    fn test_full_ban_json() {
+        assert_eq!(sample_ban().raw_json()["reason"], "spam");
+    }
+
+    #[test]
+    fn test_ban_json_shape() {
+        let ban = Ban { date: Utc.timestamp(1_600_000_000, 0), ..sample_ban() };
+        let json = ban.json().unwrap();
+        assert_eq!(json["id"], 777000);
+        assert_eq!(json["reason"], "spam");
+        assert_eq!(json["date"], 1_600_000_000);
+        assert_eq!(json, ban.raw_json());
+    }
+
+    #[test]
+    fn test_tags_json() {
+        let ban = Ban { tags: vec!["de".to_string(), "crypto".to_string()], ..sample_ban() };
+        assert_eq!(ban.raw_json()["tags"], serde_json::json!(["de", "crypto"]));
+    }
+
+    #[test]
+    fn test_null_reason_exports() {
+        let ban = Ban { reason: None, ..sample_ban() };
+        // JSON leaves a NULL reason out instead of sending null or "", CSV has an empty field
+        let serialized = serde_json::to_value(&ban).unwrap();
+        assert!(serialized.get("reason").is_none());
+        assert_eq!(serialized, ban.raw_json());
+        assert!(ban.json().unwrap().get("reason").is_none());
+        assert!(ban_csv_row(&ban).starts_with("777000,,0,"));
+        #[cfg(feature = "msgpack")]
+        {
+            let imported = import_bans_msgpack(&export_bans_msgpack(&[ban]).unwrap()).unwrap();
+            assert_eq!(imported[0].reason, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod slow_query {
+    use std::time::Duration;
+
+    use crate::database::is_slow;
+
+    #[test]
+    fn test_below_threshold() {
+        assert!(!is_slow(Duration::from_millis(999), 1000));
+    }
+
+    #[test]
+    fn test_above_threshold() {
+        assert!(is_slow(Duration::from_millis(1000), 1000));
+        assert!(is_slow(Duration::from_secs(5), 1000));
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert!(!is_slow(Duration::from_secs(60), 0));
+    }
+}
+
+#[cfg(all(test, feature = "schema"))]
+mod schema {
+    use crate::database::json_schemas;
+
+    #[test]
+    fn test_ban_schema() {
+        let schemas = json_schemas();
+        let ban = &schemas["Ban"]["properties"];
+        assert_eq!(ban["id"]["type"], "integer");
+        assert_eq!(ban["date"]["type"], "integer");
+        assert!(ban.get("reason").is_some());
+        assert!(ban.get("admin").is_some());
+    }
+
+    #[test]
+    fn test_token_schema() {
+        let schemas = json_schemas();
+        let token = &schemas["Token"]["properties"];
+        assert_eq!(token["token"]["type"], "string");
+        assert_eq!(token["retired"]["type"], "boolean");
+        assert!(token.get("permission").is_some());
+    }
+}
+
+#[cfg(test)]
+mod appeal_status {
+    use crate::database::AppealStatus;
+
+    #[test]
+    fn test_appeal_flow() {
+        assert!(AppealStatus::None.can_transition_to(AppealStatus::Pending));
+        assert!(AppealStatus::Pending.can_transition_to(AppealStatus::Approved));
+        assert!(AppealStatus::Pending.can_transition_to(AppealStatus::Denied));
+        assert!(AppealStatus::Denied.can_transition_to(AppealStatus::Pending));
+    }
+
+    #[test]
+    fn test_invalid_transitions() {
+        assert!(!AppealStatus::None.can_transition_to(AppealStatus::Approved));
+        assert!(!AppealStatus::None.can_transition_to(AppealStatus::Denied));
+        assert!(!AppealStatus::Denied.can_transition_to(AppealStatus::Approved));
+        assert!(!AppealStatus::Approved.can_transition_to(AppealStatus::Pending));
+        assert!(!AppealStatus::Pending.can_transition_to(AppealStatus::Pending));
+    }
+}
+
+#[cfg(test)]
+mod page {
+    use crate::database::Page;
+
+    #[test]
+    fn test_has_next() {
+        let page = Page::new(vec![1, 2, 3], 10, 3, 0);
+        assert!(page.has_next);
+        assert_eq!(page.total, 10);
+    }
+
+    #[test]
+    fn test_last_page() {
+        let page = Page::new(vec![10], 10, 3, 9);
+        assert!(!page.has_next);
+        assert_eq!(page.total, 10);
+    }
+
+    #[test]
+    fn test_past_the_end() {
+        let page: Page<i64> = Page::new(vec![], 10, 3, 12);
+        assert!(!page.has_next);
+    }
+}
+
+#[cfg(test)]
+mod log_queries {
+    use crate::database::loggable_query;
+
+    #[test]
+    fn test_hidden_by_default() {
+        let query = "SELECT * FROM banlist WHERE id = $1;";
+        assert!(!loggable_query(query, false).contains("banlist"));
+    }
+
+    #[test]
+    fn test_enabled() {
+        let query = "SELECT * FROM banlist WHERE id = $1;";
+        assert_eq!(loggable_query(query, true), query);
+    }
+}
+
+#[cfg(test)]
+mod legacy_import {
+    use crate::database::{ImportError, parse_legacy_bans, reject_whitelisted};
+    use crate::routes::banlist::MAX_REASON_LENGTH;
+
+    // Just the ids and reasons, in import order
+    pub fn parsed(data: &str) -> (Vec<(i64, String)>, Vec<ImportError>) {
+        let (bans, errors) = parse_legacy_bans(data);
+        (bans.into_iter().map(|ban| (ban.id, ban.reason)).collect(), errors)
+    }
+
+    #[test]
+    fn test_well_formed() {
+        let (bans, errors) = parsed("1\tspam\n2\tscam bot\n");
+        assert_eq!(bans, vec![(1, "spam".to_string()), (2, "scam bot".to_string())]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_comments_and_blank_lines() {
+        let (bans, errors) = parsed("# exported banlist\n\n1\tspam\n   \n");
+        assert_eq!(bans, vec![(1, "spam".to_string())]);
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn test_malformed_lines() {
+        let (bans, errors) = parsed("abc\tspam\n2\n3\tspam");
+        assert_eq!(bans, vec![(3, "spam".to_string())]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, 1);
+        assert_eq!(errors[1].line, 2);
+    }
+
+    #[test]
+    fn test_later_lines_win() {
+        let (bans, _) = parsed("1\tspam\n1\tscam");
+        assert_eq!(bans, vec![(1, "scam".to_string())]);
+    }
+
+    #[test]
+    fn test_reason_too_long() {
+        let (bans, errors) = parsed(&format!("1\t{}\n2\tspam", "a".repeat(MAX_REASON_LENGTH + 1)));
+        assert_eq!(bans, vec![(2, "spam".to_string())]);
+        assert_eq!(errors, vec![ImportError { line: 1, error: "reason is too long".to_string() }]);
+    }
+
+    #[test]
+    fn test_whitelisted_rows_reported() {
+        let (bans, mut errors) = parse_legacy_bans("abc\tspam\n2\tspam\n3\tscam");
+        let bans = reject_whitelisted(bans, &[2], &mut errors);
+        assert_eq!(bans.iter().map(|ban| ban.id).collect::<Vec<_>>(), vec![3]);
+        assert_eq!(errors.iter().map(|error| error.line).collect::<Vec<_>>(), vec![1, 2]);
+        assert_eq!(errors[1].error, "id `2` is whitelisted");
+    }
+}
+
+#[cfg(test)]
+mod conflict_policy {
+    use crate::database::ConflictPolicy;
+
+    #[test]
+    fn test_defaults_to_update() {
+        assert_eq!(ConflictPolicy::default(), ConflictPolicy::Update);
+    }
+
+    #[test]
+    fn test_policy_names() {
+        for (name, policy) in &[("\"update\"", ConflictPolicy::Update), ("\"ignore\"", ConflictPolicy::Ignore),
+                                ("\"error\"", ConflictPolicy::Error)] {
+            assert_eq!(serde_json::from_str::<ConflictPolicy>(name).unwrap(), *policy);
+        }
+        assert!(serde_json::from_str::<ConflictPolicy>("\"skip\"").is_err());
+    }
+}
+
+#[cfg(test)]
+mod reason_lookup {
+    use crate::database::{distinct_reasons, reason_ids_for};
+
+    #[test]
+    fn test_identical_reasons_share_a_row() {
+        let reasons = vec!["spam".to_string(), "scam".to_string(), "spam".to_string()];
+        // Only one reasons row is written for the two spam bans
+        assert_eq!(distinct_reasons(&reasons), vec!["spam", "scam"]);
+        let stored = vec![(7, "spam".to_string()), (8, "scam".to_string())];
+        assert_eq!(reason_ids_for(&reasons, &stored), vec![Some(7), Some(8), Some(7)]);
+    }
+
+    #[test]
+    fn test_missing_reason_row() {
+        let reasons = vec!["spam".to_string()];
+        assert_eq!(reason_ids_for(&reasons, &[(7, "scam".to_string())]), vec![None]);
+    }
+}
+
+#[cfg(test)]
+mod disabled_operations {
+    use crate::database::{check_operation, check_writable, OPERATIONS};
+    use crate::errors::UserError;
+
+    #[test]
+    fn test_read_only_refuses_writes() {
+        match check_writable(true) {
+            Err(UserError::ServiceReadOnly) => {}
+            other => panic!("expected read-only, got {:?}", other),
+        }
+        assert!(check_writable(false).is_ok());
+    }
+
+    #[test]
+    fn test_disabled_create_token() {
+        let disabled = vec!["create_token".to_string()];
+        match check_operation(&disabled, "create_token") {
+            Err(UserError::Forbidden) => {}
+            other => panic!("expected forbidden, got {:?}", other),
+        }
+        for operation in OPERATIONS.iter().filter(|operation| **operation != "create_token") {
+            assert!(check_operation(&disabled, operation).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_nothing_disabled() {
+        assert!(OPERATIONS.iter().all(|operation| check_operation(&[], operation).is_ok()));
+    }
+}
+
+#[cfg(test)]
+mod dedup {
+    use std::collections::HashSet;
+
+    use chrono::{TimeZone, Utc};
+
+    use crate::database::{dedup_bans, Ban};
+
+    use super::sample_ban;
+
+    fn ban(id: i64, date: i64, reason: &str) -> Ban {
+        Ban { id, reason: Some(reason.to_string()), date: Utc.timestamp(date, 0), ..sample_ban() }
+    }
+
+    #[test]
+    fn test_equality_on_id() {
+        assert_eq!(ban(1, 0, "a"), ban(1, 100, "b"));
+        assert_ne!(ban(1, 0, "a"), ban(2, 0, "a"));
+        let set: HashSet<Ban> = vec![ban(1, 0, "a"), ban(1, 5, "b"), ban(2, 0, "c")].into_iter().collect();
+        assert_eq!(set.len(), 2);
+    }
+
+    #[test]
+    fn test_dedup_keeps_most_recent() {
+        let bans = dedup_bans(vec![ban(3, 10, "old"), ban(4, 0, "other"), ban(3, 20, "new"), ban(3, 15, "middle")]);
+        assert_eq!(bans.iter().map(|b| b.id).collect::<Vec<_>>(), vec![3, 4]);
+        assert_eq!(bans[0].reason.as_deref(), Some("new"));
+    }
+}
+
+#[cfg(all(test, feature = "msgpack"))]
+mod msgpack {
+    use chrono::{TimeZone, Utc};
+
+    use crate::database::{export_bans_msgpack, import_bans_msgpack, AppealStatus, Ban};
+    use crate::errors::UserError;
+
+    use super::sample_ban;
+
+    #[test]
+    fn test_round_trip() {
+        let bans = vec![
+            Ban {
+                // Postgres keeps microseconds, they have to survive too
+                date: Utc.timestamp(1_600_000_000, 123_456_000),
+                admin: 2,
+                message: Some("hello".to_string()),
+                appeal_status: AppealStatus::Pending,
+                tags: vec!["crypto".to_string()],
+                evidence: vec!["https://t.me/c/1/2".to_string()],
+                language: Some("de".to_string()),
+                expires_at: Some(Utc.timestamp(1_700_000_000, 0)),
+                pinned: true,
+                active: false,
+                ..sample_ban()
+            },
+            Ban { id: 777001, reason: None, ..sample_ban() },
+        ];
+        let imported = import_bans_msgpack(&export_bans_msgpack(&bans).unwrap()).unwrap();
+        // Ban equality only looks at the id
+        assert_eq!(format!("{:?}", imported), format!("{:?}", bans));
+    }
+
+    #[test]
+    fn test_corrupt_timestamp_parts() {
+        for date in &[(i64::MAX, 0), (0, 2_000_000_000)] {
+            // Same field order as the records export_bans_msgpack writes
+            let record = (777000_i64, Some("spam"), *date, 1_i32, None::<String>, AppealStatus::None,
+                          Vec::<String>::new(), Vec::<String>::new(), None::<String>, None::<(i64, u32)>, false, true);
+            match import_bans_msgpack(&rmp_serde::to_vec(&vec![record]).unwrap()) {
+                Err(UserError::BadRequest(_)) => {}
+                other => panic!("expected a bad request for {:?}, got {:?}", date, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_garbage() {
+        match import_bans_msgpack(b"not msgpack") {
+            Err(UserError::BadRequest(_)) => {}
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+}
+
+#[cfg(test)]
+mod permission_summary {
+    use crate::database::PermissionSummary;
+    use crate::guards::Permission;
+
+    #[test]
+    fn test_summary_counts() {
+        let tokens = vec![
+            (Permission::Root, 1),
+            (Permission::Admin, 2),
+            (Permission::User, 3),
+            (Permission::User, 4),
+            (Permission::Root, 5),
+        ];
+        let mut summary = PermissionSummary::default();
+        for (permission, userid) in &tokens {
+            summary.add(permission, *userid);
+        }
+        assert_eq!((summary.user, summary.admin, summary.root), (2, 1, 2));
+        assert_eq!(summary.root_holders, vec![1, 5]);
+    }
+}
+
+#[cfg(test)]
+mod utc_dates {
+    use chrono::{FixedOffset, TimeZone, Utc};
+
+    use crate::database::Ban;
+
+    use super::sample_ban;
+
+    #[test]
+    fn test_date_serialized_as_utc() {
+        let local = FixedOffset::east(9 * 3600).ymd(2026, 10, 14).and_hms(9, 0, 0);
+        let ban = Ban { date: local.with_timezone(&Utc), ..sample_ban() };
+        let expected = Utc.ymd(2026, 10, 14).and_hms(0, 0, 0).timestamp();
+        assert_eq!(ban.raw_json()["date"], expected);
+        assert_eq!(serde_json::to_value(&ban).unwrap()["date"], expected);
+    }
+}
+
+#[cfg(test)]
+mod readonly_query {
+    use crate::database::readonly_query_wrapper;
+
+    #[test]
+    fn test_wraps_select() {
+        assert_eq!(
+            readonly_query_wrapper("SELECT id FROM banlist"),
+            "SELECT COALESCE(json_agg(t), '[]')::text FROM (SELECT id FROM banlist) t;"
+        );
+    }
+
+    #[test]
+    fn test_strips_trailing_semicolon() {
+        assert_eq!(
+            readonly_query_wrapper("  SELECT 1;\n"),
+            readonly_query_wrapper("SELECT 1")
+        );
+    }
+}
+
+#[cfg(test)]
+mod last_ip {
+    use std::net::IpAddr;
+
+    use crate::database::ip_changed;
+
+    #[test]
+    fn test_ip_change_detected() {
+        let home: IpAddr = "192.0.2.1".parse().unwrap();
+        let elsewhere: IpAddr = "2001:db8::1".parse().unwrap();
+        assert!(ip_changed(Some(home), Some(elsewhere)));
+        assert!(!ip_changed(Some(home), Some(home)));
+    }
+
+    #[test]
+    fn test_first_or_unknown_ip() {
+        let home: IpAddr = "192.0.2.1".parse().unwrap();
+        assert!(!ip_changed(None, Some(home)));
+        assert!(!ip_changed(Some(home), None));
+    }
+}
+
+#[cfg(test)]
+mod max_bans {
+    use crate::database::bans_over_cap;
+
+    #[test]
+    fn test_unbounded() {
+        assert_eq!(bans_over_cap(1_000_000, 0), 0);
+    }
+
+    #[test]
+    fn test_over_cap() {
+        assert_eq!(bans_over_cap(11, 10), 1);
+        assert_eq!(bans_over_cap(10, 10), 0);
+        assert_eq!(bans_over_cap(3, 10), 0);
+    }
+}
+
+#[cfg(test)]
+mod explain {
+    use crate::database::QueryOp;
+
+    #[test]
+    fn test_op_names() {
+        let ops = [("get_tokens", QueryOp::Tokens), ("get_bans", QueryOp::Bans), ("get_banned_ids", QueryOp::BannedIds),
+                   ("get_total_ban_count", QueryOp::TotalBanCount), ("get_active_bans", QueryOp::ActiveBans)];
+        for (name, op) in &ops {
+            assert_eq!(serde_json::from_value::<QueryOp>(serde_json::json!(name)).unwrap(), *op);
+        }
+    }
+
+    #[test]
+    fn test_unknown_op() {
+        // Only the parameterless reads can be explained, writes and the Rust-side names are refused
+        for name in &["add_ban", "Bans", "get_ban", ""] {
+            assert!(serde_json::from_value::<QueryOp>(serde_json::json!(name)).is_err());
+        }
+    }
+}
+
+#[cfg(test)]
+mod seed_tokens {
+    use crate::database::{missing_seeds, Token};
+    use crate::guards::Permission;
+    use crate::settings::SeedToken;
+
+    fn token(id: i32, token: &str, permission: Permission, userid: i64) -> Token {
+        Token {
+            id,
+            token: token.to_string(),
+            permission,
+            userid,
+            retired: false,
+            last_used_at: None,
+            last_ip: None,
+            last_ip_changed_at: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_seeding_twice() {
+        let seeds = vec![
+            SeedToken { userid: 1, permission: Permission::Admin, token: None },
+            SeedToken { userid: 2, permission: Permission::User, token: Some("fixed".to_string()) },
+        ];
+        assert_eq!(missing_seeds(&seeds, &[]).len(), 2);
+
+        // What the first run would have created
+        let existing = vec![
+            token(1, "generated", Permission::Admin, 1),
+            token(2, "fixed", Permission::User, 2),
+        ];
+        assert!(missing_seeds(&seeds, &existing).is_empty());
+    }
+
+    #[test]
+    fn test_retired_or_different_permission() {
+        let seeds = vec![SeedToken { userid: 1, permission: Permission::Root, token: None }];
+        let mut retired = token(1, "old", Permission::Root, 1);
+        retired.retired = true;
+        assert_eq!(missing_seeds(&seeds, &[retired, token(2, "user", Permission::User, 1)]).len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod ban_json_object {
+    use std::collections::BTreeSet;
+
+    use crate::database::{Ban, BAN_JSON_OBJECT};
+
+    use super::sample_ban;
+
+    // The keys of every jsonb_build_object call, they're the even arguments at the call's own depth
+    fn object_keys(sql: &str) -> BTreeSet<String> {
+        let mut keys = BTreeSet::new();
+        for (start, call) in sql.match_indices("jsonb_build_object(") {
+            let mut depth = 0;
+            let mut args = vec![String::new()];
+            for c in sql[start + call.len()..].chars() {
+                match c {
+                    '(' => depth += 1,
+                    ')' if depth == 0 => break,
+                    ')' => depth -= 1,
+                    ',' if depth == 0 => {
+                        args.push(String::new());
+                        continue;
+                    }
+                    _ => {}
+                }
+                args.last_mut().unwrap().push(c);
+            }
+            keys.extend(args.iter().step_by(2).map(|key| key.trim().trim_matches('\'').to_string()));
+        }
+        keys
+    }
+
+    #[test]
+    fn test_same_keys_as_struct() {
+        let ban = Ban { message: Some("message".to_string()), ..sample_ban() };
+        let json = serde_json::to_value(&ban).unwrap();
+        let keys: BTreeSet<String> = json.as_object().unwrap().keys().cloned().collect();
+        assert_eq!(object_keys(BAN_JSON_OBJECT), keys);
+    }
+}
+
+#[cfg(test)]
+mod expiry {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::database::{is_active, Ban};
+
+    use super::sample_ban;
+
+    #[test]
+    fn test_is_active() {
+        let now = Utc.timestamp(1_000_000, 0);
+        assert!(is_active(None, now));
+        assert!(is_active(Some(now + Duration::hours(1)), now));
+        assert!(!is_active(Some(now - Duration::seconds(1)), now));
+    }
+
+    #[test]
+    fn test_expired_ban_json() {
+        let expires_at = Utc::now() - Duration::days(1);
+        let ban = Ban {
+            expires_at: Some(expires_at),
+            active: is_active(Some(expires_at), Utc::now()),
+            ..sample_ban()
+        };
+        let json = ban.raw_json();
+        assert_eq!(json["active"], false);
+        assert_eq!(json["expires_at"], expires_at.timestamp());
+        assert_eq!(serde_json::to_value(&ban).unwrap()["active"], false);
+    }
+}
+
+#[cfg(test)]
+mod ban_buffer {
+    use std::time::{Duration, Instant};
+
+    use crate::database::{BanBuffer, PendingBan};
+
+    use super::sample_pending_ban;
+
+    fn ban(id: i64, reason: &str) -> PendingBan {
+        PendingBan { id, reason: reason.to_string(), tags: vec!["crypto".to_string()], ..sample_pending_ban() }
+    }
+
+    #[test]
+    fn test_flush_on_size() {
+        let mut buffer = BanBuffer::new(2, Duration::from_secs(60));
+        let now = Instant::now();
+        buffer.push(ban(1, "spam"), now);
+        assert!(!buffer.is_due(now));
+        buffer.push(ban(2, "spam"), now);
+        assert!(buffer.is_due(now));
+    }
+
+    #[test]
+    fn test_flush_on_interval() {
+        let mut buffer = BanBuffer::new(100, Duration::from_secs(1));
+        let now = Instant::now();
+        assert!(!buffer.is_due(now));
+        buffer.push(ban(1, "spam"), now);
+        assert!(!buffer.is_due(now));
+        assert!(buffer.is_due(now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_take_everything() {
+        let mut buffer = BanBuffer::new(100, Duration::from_secs(1));
+        let now = Instant::now();
+        buffer.push(ban(1, "spam"), now);
+        buffer.push(ban(2, "scam"), now);
+        buffer.push(ban(1, "crypto spam"), now);
+        let bans = buffer.take();
+        assert_eq!(bans, vec![ban(2, "scam"), ban(1, "crypto spam")]);
+        assert!(buffer.take().is_empty());
+        assert!(!buffer.is_due(now + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_requeue_keeps_failed_bans() {
+        let mut buffer = BanBuffer::new(100, Duration::from_secs(1));
+        let now = Instant::now();
+        buffer.push(ban(1, "spam"), now);
+        let failed = buffer.take();
+        buffer.push(ban(2, "scam"), now);
+        buffer.requeue(failed, now);
+        assert_eq!(buffer.take(), vec![ban(1, "spam"), ban(2, "scam")]);
+    }
+}
+
+#[cfg(test)]
+mod failed_auth {
+    use crate::database::trips_auth_threshold;
+
+    #[test]
+    fn test_threshold() {
+        assert!(!trips_auth_threshold(1, 3));
+        assert!(!trips_auth_threshold(2, 3));
+        assert!(trips_auth_threshold(3, 3));
+        assert!(trips_auth_threshold(4, 3));
+    }
+
+    #[test]
+    fn test_disabled() {
+        assert!(!trips_auth_threshold(1_000, 0));
+    }
+}
+
+#[cfg(test)]
+mod ban_ids {
+    use crate::database::{is_valid_user_id, validate_user_id};
+    use crate::errors::UserError;
+
+    #[test]
+    fn test_valid_ids() {
+        assert!(is_valid_user_id(1));
+        assert!(is_valid_user_id(5_000_000_000));
+        assert!(validate_user_id(777000).is_ok());
+    }
+
+    #[test]
+    fn test_rejects_non_positive() {
+        for id in &[0, -1, -1001234567890] {
+            match validate_user_id(*id) {
+                Err(UserError::BadRequest(_)) => {}
+                other => panic!("expected a bad request for {}, got {:?}", id, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_boundary() {
+        // find_invalid_ban_ids reports `id <= 0`, the same cut-off as the predicate
+        assert!(!is_valid_user_id(0));
+        assert!(is_valid_user_id(1));
+        assert!(validate_user_id(0).is_err());
+        assert!(validate_user_id(1).is_ok());
+    }
+
+    #[test]
+    fn test_legacy_import_rejects_non_positive() {
+        let (bans, errors) = super::legacy_import::parsed("1\tspam\n0\tspam\n-7\tspam\n");
+        assert_eq!(bans, vec![(1, "spam".to_string())]);
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].line, 2);
+    }
+}
+
+#[cfg(test)]
+mod etag {
+    use chrono::NaiveDate;
+
+    use crate::database::ban_etag;
+
+    #[test]
+    fn test_stable_without_changes() {
+        let latest = NaiveDate::from_ymd(2026, 10, 14).and_hms_micro(12, 0, 0, 250);
+        assert_eq!(ban_etag(Some(latest), 3), ban_etag(Some(latest), 3));
+        assert_eq!(ban_etag(None, 0), ban_etag(None, 0));
+    }
+
+    #[test]
+    fn test_changes_after_mutation() {
+        let latest = NaiveDate::from_ymd(2026, 10, 14).and_hms_micro(12, 0, 0, 250);
+        let updated = NaiveDate::from_ymd(2026, 10, 14).and_hms_micro(12, 0, 0, 251);
+        let before = ban_etag(Some(latest), 3);
+        assert_ne!(before, ban_etag(Some(updated), 3));
+        // Removing a row doesn't necessarily move the latest change
+        assert_ne!(before, ban_etag(Some(latest), 2));
+        assert_ne!(ban_etag(None, 0), ban_etag(Some(latest), 1));
+    }
+
+    #[test]
+    fn test_quoted() {
+        let etag = ban_etag(None, 0);
+        assert!(etag.starts_with('"') && etag.ends_with('"'));
+    }
+}
+
+#[cfg(test)]
+mod sanitize_reason {
+    use crate::database::sanitize_reason;
+
+    #[test]
+    fn test_null_byte() {
+        assert_eq!(sanitize_reason("spam\0bot"), "spambot");
+    }
+
+    #[test]
+    fn test_escape_sequence() {
+        assert_eq!(sanitize_reason("\u{1b}[31mscam\u{1b}[0m"), "scam");
+        assert_eq!(sanitize_reason("a\u{1b}b"), "ab");
+        assert_eq!(sanitize_reason("\u{7}bell\u{7f}"), "bell");
+    }
+
+    #[test]
+    fn test_keeps_whitespace() {
+        assert_eq!(sanitize_reason("spam\tbot\nläuft\r"), "spam\tbot\nläuft\r");
+    }
+
+    #[test]
+    fn test_legacy_import() {
+        let (bans, errors) = super::legacy_import::parsed("1\tspam\0\n2\t\u{1b}[1m\u{1b}[0m\n");
+        assert_eq!(bans, vec![(1, "spam".to_string())]);
+        assert_eq!(errors.len(), 1);
+    }
+}
+
+#[cfg(test)]
+mod pinned {
+    use chrono::{Duration, Utc};
+
+    use crate::database::Ban;
+
+    use super::sample_ban;
+
+    #[test]
+    fn test_pinned_json() {
+        let ban = Ban { expires_at: Some(Utc::now() - Duration::days(1)), pinned: true, ..sample_ban() };
+        assert_eq!(ban.raw_json()["pinned"], true);
+        assert_eq!(ban.raw_json()["active"], true);
+    }
+}
+
+#[cfg(test)]
+mod clock {
+    use std::sync::Mutex;
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+
+    use crate::database::{is_active, Clock, DatabaseClock};
+
+    struct MockClock {
+        now: Mutex<DateTime<Utc>>,
+    }
+
+    impl MockClock {
+        fn advance(&self, by: Duration) {
+            let mut now = self.now.lock().unwrap();
+            *now = *now + by;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> DateTime<Utc> {
+            *self.now.lock().unwrap()
+        }
+    }
+
+    #[test]
+    fn test_advancing_expires_ban() {
+        let clock = MockClock { now: Mutex::new(Utc.timestamp(1_000_000, 0)) };
+        let expires_at = Some(Utc.timestamp(1_000_000, 0) + Duration::hours(1));
+        assert!(is_active(expires_at, clock.now()));
+
+        clock.advance(Duration::minutes(59));
+        assert!(is_active(expires_at, clock.now()));
+        clock.advance(Duration::minutes(1));
+        assert!(!is_active(expires_at, clock.now()));
+    }
+
+    #[test]
+    fn test_database_clock_before_connecting() {
+        let before = Utc::now();
+        let now = DatabaseClock.now();
+        assert!(before <= now && now <= Utc::now());
+    }
+}
+
+#[cfg(test)]
+mod ban_query {
+    use crate::database::{AppealStatus, BanQuery, BanSort};
+
+    #[test]
+    fn test_no_filters() {
+        let (conditions, params) = BanQuery::default().where_clause();
+        assert_eq!(conditions, "deleted_at IS NULL");
+        assert!(params.is_empty());
+        assert_eq!(BanQuery::default().order_by(), "id ASC");
+    }
+
+    // The $n placeholders in the order they appear
+    fn placeholders(conditions: &str) -> Vec<usize> {
+        conditions.split('$').skip(1)
+            .map(|rest| rest.chars().take_while(char::is_ascii_digit).collect::<String>().parse().unwrap())
+            .collect()
+    }
+
+    fn debug_params(query: &BanQuery) -> (Vec<usize>, Vec<String>) {
+        let (conditions, params) = query.where_clause();
+        (placeholders(&conditions), params.iter().map(|param| format!("{:?}", param)).collect())
+    }
+
+    #[test]
+    fn test_combined_filters() {
+        let query = BanQuery {
+            from: Some(1_600_000_000),
+            to: Some(1_700_000_000),
+            tag: Some("crypto".to_string()),
+            appeal_status: Some(AppealStatus::Pending),
+            search: Some("50%_off".to_string()),
+            ..BanQuery::default()
+        };
+        let (placeholders, params) = debug_params(&query);
+        assert_eq!(placeholders, vec![1, 2, 3, 4, 5]);
+        assert_eq!(params, vec!["1600000000", "1700000000", "\"crypto\"", "Pending", r#""%50\\%\\_off%""#]);
+    }
+
+    #[test]
+    fn test_skips_unset_filters() {
+        let query = BanQuery { language: Some("EN".to_string()), admin: Some(2), ..BanQuery::default() };
+        let (placeholders, params) = debug_params(&query);
+        assert_eq!(placeholders, vec![1, 2]);
+        assert_eq!(params, vec!["\"en\"", "2"]);
+    }
+
+    #[test]
+    fn test_sort_whitelist() {
+        let query: BanQuery = serde_json::from_value(serde_json::json!({"sort": "date", "descending": true})).unwrap();
+        assert_eq!(query.sort, BanSort::Date);
+        assert_eq!(query.order_by(), "date DESC, id DESC");
+        assert!(serde_json::from_value::<BanQuery>(serde_json::json!({"sort": "reason; DROP TABLE banlist"})).is_err());
+    }
+}
+
+#[cfg(test)]
+mod csv_export {
+    use chrono::{TimeZone, Utc};
+
+    use crate::database::{ban_csv_row, AppealStatus, Ban, BAN_CSV_HEADER};
+
+    use super::sample_ban;
+
+    fn ban(id: i64, reason: Option<&str>, tags: Vec<&str>) -> Ban {
+        Ban {
+            id,
+            reason: reason.map(str::to_string),
+            date: Utc.timestamp(1_600_000_000, 0),
+            appeal_status: AppealStatus::Pending,
+            tags: tags.into_iter().map(str::to_string).collect(),
+            language: Some("en".to_string()),
+            ..sample_ban()
+        }
+    }
+
+    #[test]
+    fn test_row_matches_header() {
+        let row = ban_csv_row(&ban(777000, Some("spam"), vec!["crypto", "bot"]));
+        assert_eq!(row, "777000,spam,1600000000,1,,Pending,crypto;bot,en,true\n");
+        assert_eq!(row.split(',').count(), BAN_CSV_HEADER.split(',').count());
+    }
+
+    #[test]
+    fn test_reason_with_separators() {
+        let row = ban_csv_row(&ban(777000, Some("spam, \"airdrop\""), vec![]));
+        assert_eq!(row, "777000,\"spam, \"\"airdrop\"\"\",1600000000,1,,Pending,,en,true\n");
+    }
+
+    #[test]
+    fn test_null_reason() {
+        assert!(ban_csv_row(&ban(777000, None, vec![])).starts_with("777000,,1600000000,"));
+    }
+}
+
+#[cfg(test)]
+mod ranked_search {
+    use crate::database::ranked_search_terms;
+
+    #[test]
+    fn test_any_of_the_words() {
+        assert_eq!(ranked_search_terms("Crypto airdrop scam").as_deref(), Some("crypto | airdrop | scam"));
+        assert_eq!(ranked_search_terms("spam spam SPAM").as_deref(), Some("spam"));
+        assert_eq!(ranked_search_terms("спам бот").as_deref(), Some("спам | бот"));
+    }
+
+    #[test]
+    fn test_strips_tsquery_syntax() {
+        assert_eq!(ranked_search_terms("scam & !(bot):*").as_deref(), Some("scam | bot"));
+        assert_eq!(ranked_search_terms(" &|!() "), None);
+    }
+}
@@ -0,0 +1,37 @@
+#[cfg(test)]
+mod status {
+    use actix_web::http::StatusCode;
+
+    use crate::errors::UserError;
+
+    #[test]
+    fn test_variant_statuses() {
+        let cases = vec![
+            (UserError::Internal, StatusCode::INTERNAL_SERVER_ERROR),
+            (UserError::NotFound, StatusCode::NOT_FOUND),
+            (UserError::BadRequest("reason"), StatusCode::BAD_REQUEST),
+            (UserError::MethodNotAllowed, StatusCode::METHOD_NOT_ALLOWED),
+            (UserError::Unauthorized, StatusCode::UNAUTHORIZED),
+            (UserError::Forbidden, StatusCode::FORBIDDEN),
+            (UserError::Conflict, StatusCode::CONFLICT),
+            (UserError::TooManyRequests { until: 0 }, StatusCode::TOO_MANY_REQUESTS),
+            (UserError::ServiceReadOnly, StatusCode::SERVICE_UNAVAILABLE),
+            (UserError::Unavailable, StatusCode::SERVICE_UNAVAILABLE),
+            (UserError::Timeout, StatusCode::GATEWAY_TIMEOUT),
+        ];
+        for (error, status) in cases {
+            assert_eq!(error.status(), status, "{:?}", error);
+            assert_eq!(error.to_response().status(), status, "{:?}", error);
+            assert_eq!(error.to_json()["code"], status.as_u16(), "{:?}", error);
+            assert_eq!(error.to_json()["error"], status.canonical_reason().unwrap(), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn test_variant_details() {
+        assert_eq!(UserError::BadRequest("limit has to be greater than 0").to_json()["reason"],
+                   "limit has to be greater than 0");
+        assert_eq!(UserError::TooManyRequests { until: 1_600_000_000 }.to_json()["until"], 1_600_000_000);
+        assert_eq!(UserError::NotFound.to_json().as_object().unwrap().len(), 2);
+    }
+}
@@ -0,0 +1,219 @@
+#[cfg(test)]
+mod authenticate {
+    use chrono::{Duration, TimeZone, Utc};
+
+    use crate::database::{check_owner_not_banned, check_token, Ban, Token};
+    use crate::errors::UserError;
+    use crate::guards::Permission;
+    use crate::tests::database::sample_ban;
+
+    fn token(retired: bool) -> Token {
+        Token {
+            id: 1,
+            token: "token".to_string(),
+            permission: Permission::User,
+            userid: 777000,
+            retired,
+            last_used_at: None,
+            last_ip: None,
+            last_ip_changed_at: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_valid_token() {
+        assert_eq!(check_token(Some(token(false))).unwrap().id, 1);
+    }
+
+    #[test]
+    fn test_revoked_token() {
+        match check_token(Some(token(true))) {
+            Err(UserError::Unauthorized) => (),
+            _ => panic!("revoked token was accepted"),
+        }
+    }
+
+    fn owner_ban(active: bool) -> Ban {
+        Ban {
+            expires_at: if active { None } else { Some(Utc.timestamp(0, 0) + Duration::hours(1)) },
+            active,
+            ..sample_ban()
+        }
+    }
+
+    #[test]
+    fn test_banned_owner() {
+        let mut banned = token(false);
+        banned.id = 2;
+        match check_owner_not_banned(banned, Some(&owner_ban(true))) {
+            Err(UserError::Forbidden) => (),
+            _ => panic!("token of a banned owner was accepted"),
+        }
+    }
+
+    #[test]
+    fn test_owner_ban_expired() {
+        let mut token = token(false);
+        token.id = 2;
+        assert_eq!(check_owner_not_banned(token, Some(&owner_ban(false))).unwrap().id, 2);
+    }
+
+    #[test]
+    fn test_genesis_owner_banned() {
+        assert_eq!(check_owner_not_banned(token(false), Some(&owner_ban(true))).unwrap().id, 1);
+        assert_eq!(check_owner_not_banned(token(false), None).unwrap().id, 1);
+    }
+
+    #[test]
+    fn test_unknown_token() {
+        match check_token(None) {
+            Err(UserError::Unauthorized) => (),
+            _ => panic!("unknown token was accepted"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod require_permission {
+    use actix_web::http::StatusCode;
+
+    use crate::database::Token;
+    use crate::errors::UserError;
+    use crate::guards::{Permission, require_permission};
+
+    fn token(permission: Permission) -> Token {
+        Token {
+            id: 1,
+            token: "token".to_string(),
+            permission,
+            userid: 777000,
+            retired: false,
+            last_used_at: None,
+            last_ip: None,
+            last_ip_changed_at: None,
+            note: None,
+        }
+    }
+
+    #[test]
+    fn test_allowed() {
+        assert!(require_permission(&token(Permission::User), Permission::User).is_ok());
+        assert!(require_permission(&token(Permission::Admin), Permission::User).is_ok());
+        assert!(require_permission(&token(Permission::Admin), Permission::Admin).is_ok());
+        assert!(require_permission(&token(Permission::Root), Permission::User).is_ok());
+        assert!(require_permission(&token(Permission::Root), Permission::Admin).is_ok());
+        assert!(require_permission(&token(Permission::Root), Permission::Root).is_ok());
+    }
+
+    #[test]
+    fn test_denied() {
+        for (permission, min) in [
+            (Permission::User, Permission::Admin),
+            (Permission::User, Permission::Root),
+            (Permission::Admin, Permission::Root),
+        ] {
+            match require_permission(&token(permission), min) {
+                Err(e @ UserError::Forbidden) => assert_eq!(e.to_response().status(), StatusCode::FORBIDDEN),
+                other => panic!("expected forbidden, got {:?}", other),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod permission_distance {
+    use crate::guards::Permission;
+
+    #[test]
+    fn test_distance_matrix() {
+        let permissions = [Permission::Unknown, Permission::User, Permission::Admin, Permission::Root];
+        let expected = [
+            [0, 1, 2, 3],
+            [-1, 0, 1, 2],
+            [-2, -1, 0, 1],
+            [-3, -2, -1, 0],
+        ];
+        for (from, row) in permissions.iter().zip(expected.iter()) {
+            for (to, distance) in permissions.iter().zip(row.iter()) {
+                assert_eq!(from.distance_to(to), *distance, "{:?} to {:?}", from, to);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod permission_casing {
+    use crate::guards::Permission;
+
+    #[test]
+    fn test_wire_name() {
+        assert_eq!(Permission::Admin.wire_name(false), "Admin");
+        assert_eq!(Permission::Admin.wire_name(true), "admin");
+        assert_eq!(Permission::Root.wire_name(true), "root");
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for lowercase in [false, true] {
+            for permission in [Permission::User, Permission::Admin, Permission::Root] {
+                let wire = serde_json::to_string(permission.wire_name(lowercase)).unwrap();
+                assert_eq!(serde_json::from_str::<Permission>(&wire).unwrap(), permission);
+            }
+        }
+    }
+
+    #[test]
+    fn test_default_casing() {
+        assert_eq!(serde_json::to_value(Permission::User).unwrap(), "User");
+    }
+}
+
+#[cfg(test)]
+mod unknown_permission {
+    use bytes::BytesMut;
+    use postgres_types::{FromSql, Kind, ToSql, Type};
+
+    use crate::database::Token;
+    use crate::guards::{Permission, require_permission};
+
+    // The database enum after a newer deploy added a value
+    fn permission_type() -> Type {
+        let variants = ["User", "Admin", "Root", "Moderator"].iter().map(|v| v.to_string()).collect();
+        Type::new("permission".to_string(), 0, Kind::Enum(variants), "public".to_string())
+    }
+
+    #[test]
+    fn test_unknown_value() {
+        let ty = permission_type();
+        assert!(<Permission as FromSql>::accepts(&ty));
+        assert_eq!(Permission::from_sql(&ty, b"Moderator").unwrap(), Permission::Unknown);
+        assert_eq!(Permission::from_sql(&ty, b"Admin").unwrap(), Permission::Admin);
+    }
+
+    #[test]
+    fn test_unknown_grants_nothing() {
+        let token = Token {
+            id: 1,
+            token: "token".to_string(),
+            permission: Permission::Unknown,
+            userid: 777000,
+            retired: false,
+            last_used_at: None,
+            last_ip: None,
+            last_ip_changed_at: None,
+            note: None,
+        };
+        assert!(require_permission(&token, Permission::User).is_err());
+        assert!(serde_json::from_str::<Permission>("\"Unknown\"").is_err());
+    }
+
+    #[test]
+    fn test_unknown_not_written() {
+        let ty = permission_type();
+        let mut out = BytesMut::new();
+        assert!(Permission::Unknown.to_sql(&ty, &mut out).is_err());
+        Permission::Root.to_sql(&ty, &mut out).unwrap();
+        assert_eq!(&out[..], b"Root");
+    }
+}
@@ -1,2 +1,14 @@
+mod banlist;
+mod database;
+mod errors;
+mod guards;
 mod root;
+mod scheduler;
+mod scopes;
+mod settings;
+mod store;
+mod token_generator;
 mod tokens;
+mod upstream;
+mod utils;
+mod whitelist;
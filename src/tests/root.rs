@@ -56,6 +56,25 @@ mod post {
         let resp = test::block_on(app.call(req)).unwrap();
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[test]
+    fn test_query_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/query")
+                    .route(web::post().to(routes::root::query)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::post()
+            .uri("/query")
+            .set_payload("SELECT 1")
+            .to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
 
 #[cfg(test)]
@@ -147,3 +166,47 @@ mod delete {
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
 }
+
+#[cfg(test)]
+mod lockdown {
+    use actix_service::Service;
+    use actix_web::{App, HttpResponse, web};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::routes;
+
+    #[test]
+    fn test_lockdown_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(web::resource("/lockdown").route(web::put().to(routes::root::put_lockdown))),
+        );
+        // Create request object
+        let req = test::TestRequest::put().uri("/lockdown").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    // The flag is passed in, flipping the global one would race the other tests
+    fn gated_status(read_only: bool, req: test::TestRequest) -> StatusCode {
+        let mut app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| routes::root::gate_writes(read_only, req, srv))
+                .service(
+                    web::resource("/banlist")
+                        .route(web::get().to(HttpResponse::Ok))
+                        .route(web::post().to(HttpResponse::Created)),
+                ),
+        );
+        test::block_on(app.call(req.to_request())).unwrap().status()
+    }
+
+    #[test]
+    fn test_read_only_gate() {
+        assert_eq!(gated_status(true, test::TestRequest::post().uri("/banlist")), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(gated_status(true, test::TestRequest::get().uri("/banlist")), StatusCode::OK);
+        assert_eq!(gated_status(false, test::TestRequest::post().uri("/banlist")), StatusCode::CREATED);
+    }
+}
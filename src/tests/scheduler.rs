@@ -0,0 +1,39 @@
+#[cfg(test)]
+mod tasks {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::scheduler::Scheduler;
+
+    #[test]
+    fn test_task_fires() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let mut scheduler = Scheduler::new();
+        scheduler.register("count", Duration::from_millis(20), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        });
+        let handle = scheduler.start();
+        thread::sleep(Duration::from_millis(100));
+        handle.stop();
+        assert!(runs.load(Ordering::SeqCst) >= 1);
+    }
+
+    #[test]
+    fn test_task_survives_error() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let counter = runs.clone();
+        let mut scheduler = Scheduler::new();
+        scheduler.register("failing", Duration::from_millis(10), move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Err("boom".into())
+        });
+        let handle = scheduler.start();
+        thread::sleep(Duration::from_millis(100));
+        handle.stop();
+        assert!(runs.load(Ordering::SeqCst) >= 2);
+    }
+}
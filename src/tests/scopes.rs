@@ -0,0 +1,60 @@
+#[cfg(test)]
+mod no_auth {
+    use actix_service::Service;
+    use actix_web::{App, web};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::routes;
+
+    #[test]
+    fn test_get_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/scopes/{scope}/banlist/{id}")
+                    .route(web::get().to(routes::scopes::get_scoped_ban)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/scopes/-1001234/banlist/777000").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_put_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/scopes/{scope}/banlist/{id}")
+                    .route(web::put().to(routes::scopes::put_scoped_ban)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::put()
+            .uri("/scopes/-1001234/banlist/777000")
+            .set_json(&serde_json::json!({"reason": "spam"}))
+            .to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_delete_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/scopes/{scope}/banlist/{id}")
+                    .route(web::delete().to(routes::scopes::delete_scoped_ban)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::delete().uri("/scopes/-1001234/banlist/777000").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
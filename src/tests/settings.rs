@@ -0,0 +1,202 @@
+#[cfg(test)]
+mod table_prefix {
+    use crate::settings::{is_safe_identifier, Settings};
+
+    #[test]
+    fn test_no_prefix() {
+        let settings = Settings::default();
+        assert_eq!(settings.database.table("banlist"), "banlist");
+    }
+
+    #[test]
+    fn test_prefix() {
+        let mut settings = Settings::default();
+        settings.database.table_prefix = "staging_".to_string();
+        assert_eq!(settings.database.table("banlist"), "staging_banlist");
+        assert_eq!(settings.database.table("tokens"), "staging_tokens");
+    }
+
+    #[test]
+    fn test_prefix_validation() {
+        assert!(is_safe_identifier(""));
+        assert!(is_safe_identifier("staging_2"));
+        assert!(!is_safe_identifier("staging-"));
+        assert!(!is_safe_identifier("x; DROP TABLE banlist; --"));
+    }
+
+    #[test]
+    fn test_invalid_prefix_fails_validation() {
+        let mut settings = Settings::default();
+        settings.database.table_prefix = "staging-".to_string();
+        assert!(settings.validate().is_err());
+    }
+}
+
+#[cfg(test)]
+mod validate {
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_default_is_valid() {
+        assert!(Settings::default().validate().is_ok());
+    }
+
+    #[test]
+    fn test_empty_host() {
+        let mut settings = Settings::default();
+        settings.database.host = String::new();
+        let err = settings.validate().unwrap_err().to_string();
+        assert!(err.contains("database.host"));
+    }
+
+    #[test]
+    fn test_zero_ports() {
+        let mut settings = Settings::default();
+        settings.database.port = 0;
+        settings.server.port = 0;
+        let err = settings.validate().unwrap_err().to_string();
+        assert!(err.contains("database.port"));
+        assert!(err.contains("server.port"));
+    }
+
+    #[test]
+    fn test_reports_every_problem() {
+        let mut settings = Settings::default();
+        settings.database.name = String::new();
+        settings.database.username = String::new();
+        settings.general.token_size = 0;
+        let err = settings.validate().unwrap_err().to_string();
+        assert!(err.contains("database.name"));
+        assert!(err.contains("database.username"));
+        assert!(err.contains("general.token_size"));
+    }
+
+    #[test]
+    fn test_permission_prefix_characters() {
+        let mut settings = Settings::default();
+        settings.permission_prefixes.root = "root_".to_string();
+        assert!(settings.validate().is_ok());
+        settings.permission_prefixes.admin = "adm in".to_string();
+        let err = settings.validate().unwrap_err().to_string();
+        assert!(err.contains("permission_prefixes.admin"));
+    }
+
+    #[test]
+    fn test_unknown_disabled_operation() {
+        let mut settings = Settings {
+            disabled_operations: vec!["create_token".to_string(), "update_token".to_string(), "set_appeal_status".to_string()],
+            ..Settings::default()
+        };
+        assert!(settings.validate().is_ok());
+        settings.disabled_operations.push("drop_tables".to_string());
+        let err = settings.validate().unwrap_err().to_string();
+        assert!(err.contains("drop_tables"));
+    }
+}
+
+#[cfg(test)]
+mod token_entropy {
+    use crate::settings::{collision_probability, MAX_COLLISION_PROBABILITY, Settings};
+
+    #[test]
+    fn test_default_entropy() {
+        assert!(Settings::default().token_collision_probability() < MAX_COLLISION_PROBABILITY);
+    }
+
+    #[test]
+    fn test_low_entropy() {
+        assert!(collision_probability(4, 10_000) > 0.9);
+        assert!(collision_probability(8, 100_000) > MAX_COLLISION_PROBABILITY);
+    }
+
+    #[test]
+    fn test_single_token() {
+        assert_eq!(collision_probability(1, 1), 0.0);
+    }
+
+    #[test]
+    fn test_strict_entropy() {
+        let mut settings = Settings::default();
+        settings.general.token_size = 8;
+        assert!(settings.validate().is_ok());
+        settings.general.strict_token_entropy = true;
+        let err = settings.validate().unwrap_err().to_string();
+        assert!(err.contains("general.token_size"));
+    }
+}
+
+#[cfg(test)]
+mod masked_dsn {
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_password_masked() {
+        let mut settings = Settings::default();
+        settings.database.username = "spamwatch".to_string();
+        settings.database.password = "correct horse battery staple".to_string();
+        let dsn = settings.database.masked_dsn();
+        assert!(!dsn.contains(&settings.database.password));
+        assert!(dsn.contains("spamwatch:****@"));
+    }
+
+    #[test]
+    fn test_debug_has_no_password() {
+        let mut settings = Settings::default();
+        settings.database.password = "hunter2".to_string();
+        assert!(!format!("{:?}", settings).contains("hunter2"));
+    }
+}
+
+#[cfg(test)]
+mod seed_tokens {
+    use config::{Config, File, FileFormat};
+
+    use crate::guards::Permission;
+    use crate::settings::Settings;
+
+    #[test]
+    fn test_load_seed_tokens() {
+        let mut config = Config::default();
+        config.merge(Config::try_from(&Settings::default()).unwrap()).unwrap();
+        config.merge(File::from_str(r#"
+            [[seed_tokens]]
+            userid = 777000
+            permission = "Admin"
+            token = "fixed"
+        "#, FileFormat::Toml)).unwrap();
+        let settings: Settings = config.try_into().unwrap();
+        assert_eq!(settings.seed_tokens.len(), 1);
+        assert_eq!(settings.seed_tokens[0].permission, Permission::Admin);
+        assert_eq!(settings.seed_tokens[0].token.as_deref(), Some("fixed"));
+        assert!(!format!("{:?}", settings).contains("fixed"));
+    }
+}
+
+#[cfg(test)]
+mod default_permission {
+    use crate::guards::Permission;
+    use crate::settings::{parse_permission, Settings};
+
+    #[test]
+    fn test_default() {
+        let settings = Settings::default();
+        assert_eq!(parse_permission(&settings.general.default_permission), Some(Permission::User));
+    }
+
+    #[test]
+    fn test_configured() {
+        let mut settings = Settings::default();
+        settings.general.default_permission = "admin".to_string();
+        assert!(settings.validate().is_ok());
+        assert_eq!(parse_permission(&settings.general.default_permission), Some(Permission::Admin));
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut settings = Settings::default();
+        for name in &["Moderator", "Unknown", ""] {
+            settings.general.default_permission = name.to_string();
+            assert!(settings.validate().is_err(), "{} was accepted", name);
+        }
+    }
+}
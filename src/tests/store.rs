@@ -0,0 +1,469 @@
+#[cfg(test)]
+mod in_memory {
+    use std::collections::{BTreeMap, BTreeSet};
+
+    use chrono::{NaiveDateTime, TimeZone, Utc};
+
+    use crate::database::{AppealStatus, Ban, BanFields, BanOutcome, ConflictPolicy, PendingBan, UpsertOutcome};
+    use crate::errors::UserError;
+    use crate::routes;
+    use crate::store::Store;
+    use crate::upstream::Upstream;
+
+    #[derive(Default)]
+    struct InMemoryStore {
+        bans: BTreeMap<i64, Ban>,
+        scoped: BTreeMap<(i64, i64), Ban>,
+        whitelist: BTreeSet<i64>,
+    }
+
+    impl Store for InMemoryStore {
+        fn get_ban(&mut self, user_id: i64) -> Result<Option<Ban>, UserError> {
+            Ok(self.bans.get(&user_id).cloned())
+        }
+
+        fn get_ban_admin(&mut self, user_id: i64) -> Result<Option<i32>, UserError> {
+            Ok(self.bans.get(&user_id).map(|ban| ban.admin))
+        }
+
+        fn add_ban(&mut self, ban: &PendingBan, policy: ConflictPolicy) -> Result<bool, UserError> {
+            if self.bans.contains_key(&ban.id) && policy != ConflictPolicy::Update {
+                return Ok(false);
+            }
+            self.bans.insert(ban.id, Ban {
+                id: ban.id,
+                reason: Some(ban.reason.clone()),
+                date: Utc::now(),
+                admin: ban.admin_token,
+                message: ban.message.clone(),
+                appeal_status: AppealStatus::None,
+                tags: ban.tags.clone(),
+                evidence: ban.evidence.clone(),
+                language: ban.language.clone(),
+                expires_at: None,
+                pinned: false,
+                active: true,
+            });
+            Ok(true)
+        }
+
+        fn ban_unless_whitelisted(&mut self, ban: &PendingBan) -> Result<bool, UserError> {
+            if self.whitelist.contains(&ban.id) {
+                return Ok(false);
+            }
+            self.add_ban(ban, ConflictPolicy::Update)
+        }
+
+        fn upsert_bans_reporting(&mut self, bans: &[PendingBan]) -> Result<Vec<BanOutcome>, UserError> {
+            let mut outcomes = Vec::new();
+            for ban in bans {
+                let inserted = !self.bans.contains_key(&ban.id);
+                self.add_ban(ban, ConflictPolicy::Update)?;
+                outcomes.push(BanOutcome::new(ban.id, inserted));
+            }
+            Ok(outcomes)
+        }
+
+        fn add_evidence(&mut self, user_id: i64, link: &str) -> Result<bool, UserError> {
+            match self.bans.get_mut(&user_id) {
+                Some(ban) => {
+                    if !ban.evidence.iter().any(|existing| existing == link) {
+                        ban.evidence.push(link.to_string());
+                    }
+                    Ok(true)
+                }
+                None => Ok(false),
+            }
+        }
+
+        fn get_bans_by_language(&mut self, _fields: BanFields, language: &str) -> Result<Vec<Ban>, UserError> {
+            Ok(self.bans.values().filter(|ban| ban.language.as_deref() == Some(language)).cloned().collect())
+        }
+
+        fn sample_bans(&mut self, n: i64) -> Result<Vec<Ban>, UserError> {
+            Ok(self.bans.values().take(n as usize).cloned().collect())
+        }
+
+        fn delete_ban(&mut self, user_id: i64, _admin_token: i32) -> Result<(), UserError> {
+            self.bans.remove(&user_id);
+            Ok(())
+        }
+
+        fn get_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<Option<Ban>, UserError> {
+            Ok(self.scoped.get(&(user_id, scope_id)).cloned())
+        }
+
+        fn add_scoped_ban(&mut self, scope_id: i64, ban: &PendingBan) -> Result<(), UserError> {
+            self.scoped.insert((ban.id, scope_id), Ban {
+                id: ban.id,
+                reason: Some(ban.reason.clone()),
+                date: Utc::now(),
+                admin: ban.admin_token,
+                message: ban.message.clone(),
+                appeal_status: AppealStatus::None,
+                tags: vec![],
+                evidence: vec![],
+                language: None,
+                expires_at: None,
+                pinned: false,
+                active: true,
+            });
+            Ok(())
+        }
+
+        fn delete_scoped_ban(&mut self, scope_id: i64, user_id: i64) -> Result<bool, UserError> {
+            Ok(self.scoped.remove(&(user_id, scope_id)).is_some())
+        }
+
+        fn get_banned_ids(&mut self) -> Result<Vec<i64>, UserError> {
+            Ok(self.bans.keys().cloned().collect())
+        }
+
+        fn get_total_ban_count(&mut self) -> Result<i64, UserError> {
+            Ok(self.bans.len() as i64)
+        }
+
+        fn is_whitelisted(&mut self, user_id: i64) -> Result<bool, UserError> {
+            Ok(self.whitelist.contains(&user_id))
+        }
+
+        fn ban_date_range(&mut self) -> Result<Option<(NaiveDateTime, NaiveDateTime)>, UserError> {
+            let dates = self.bans.values().map(|ban| ban.date.naive_utc());
+            Ok(dates.clone().min().zip(dates.max()))
+        }
+    }
+
+    fn bans(json: serde_json::Value) -> Vec<routes::banlist::CreateBan> {
+        serde_json::from_value(json).unwrap()
+    }
+
+    #[test]
+    fn test_ban_round_trip() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "spam", "tags": ["crypto"]}]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+
+        let ban = routes::banlist::find_ban(&mut store, 777000).unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("spam"));
+        assert_eq!(ban.tags, vec!["crypto".to_string()]);
+        assert_eq!(routes::root::stats_json(&mut store).unwrap()["total_ban_count"], 1);
+        assert_eq!(routes::banlist::banned_ids_body(&mut store).unwrap(), "777000");
+    }
+
+    #[test]
+    fn test_conflict_policy() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "spam"}]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Error).unwrap();
+        match routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Error) {
+            Err(UserError::Conflict) => {}
+            other => panic!("expected a conflict, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_ignore_keeps_existing_ban() {
+        let mut store = InMemoryStore::default();
+        routes::banlist::add_bans(&mut store, &bans(serde_json::json!([{"id": 777000, "reason": "spam"}])), 1,
+                                  ConflictPolicy::Update).unwrap();
+        routes::banlist::add_bans(&mut store, &bans(serde_json::json!([{"id": 777000, "reason": "scam"}])), 2,
+                                  ConflictPolicy::Ignore).unwrap();
+        assert_eq!(store.bans[&777000].reason.as_deref(), Some("spam"));
+        assert_eq!(store.bans[&777000].admin, 1);
+    }
+
+    #[test]
+    fn test_ban_unless_whitelisted() {
+        let mut store = InMemoryStore::default();
+        store.whitelist.insert(777000);
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "spam"},
+            {"id": 777001, "reason": "spam"}
+        ]));
+        let skipped = routes::banlist::add_bans_unless_whitelisted(&mut store, &new, 1).unwrap();
+        assert_eq!(skipped, vec![777000]);
+        assert!(!store.bans.contains_key(&777000));
+        assert!(store.bans.contains_key(&777001));
+    }
+
+    #[test]
+    fn test_upsert_reports_outcomes() {
+        let mut store = InMemoryStore::default();
+        routes::banlist::add_bans(&mut store, &bans(serde_json::json!([{"id": 777000, "reason": "spam"}])), 1,
+                                  ConflictPolicy::Update).unwrap();
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "scam"},
+            {"id": 777001, "reason": "spam"}
+        ]));
+        let outcomes = routes::banlist::add_bans_reporting(&mut store, &new, 1).unwrap();
+        assert_eq!(outcomes, vec![
+            BanOutcome { id: 777000, outcome: UpsertOutcome::Updated },
+            BanOutcome { id: 777001, outcome: UpsertOutcome::Inserted },
+        ]);
+        assert_eq!(store.bans[&777000].reason.as_deref(), Some("scam"));
+    }
+
+    #[test]
+    fn test_upsert_reports_duplicate_id_once() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "spam"},
+            {"id": 777001, "reason": "spam"},
+            {"id": 777000, "reason": "scam"}
+        ]));
+        let outcomes = routes::banlist::add_bans_reporting(&mut store, &new, 1).unwrap();
+        assert_eq!(outcomes, vec![
+            BanOutcome { id: 777001, outcome: UpsertOutcome::Inserted },
+            BanOutcome { id: 777000, outcome: UpsertOutcome::Inserted },
+        ]);
+        assert_eq!(store.bans[&777000].reason.as_deref(), Some("scam"));
+    }
+
+    #[test]
+    fn test_ban_date_range() {
+        let mut store = InMemoryStore::default();
+        assert_eq!(store.ban_date_range().unwrap(), None);
+        assert!(routes::root::stats_json(&mut store).unwrap()["oldest_ban"].is_null());
+
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "spam"},
+            {"id": 777001, "reason": "spam"},
+            {"id": 777002, "reason": "spam"}
+        ]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+        for (id, date) in [(777000, 1_600_000_000), (777001, 1_500_000_000), (777002, 1_700_000_000)] {
+            store.bans.get_mut(&id).unwrap().date = Utc.timestamp(date, 0);
+        }
+        let stats = routes::root::stats_json(&mut store).unwrap();
+        assert_eq!(stats["oldest_ban"], 1_500_000_000);
+        assert_eq!(stats["newest_ban"], 1_700_000_000);
+    }
+
+    struct MockUpstream;
+
+    impl Upstream for MockUpstream {
+        fn get_ban(&self, user_id: i64) -> Option<Ban> {
+            if user_id != 777001 {
+                return None;
+            }
+            Some(Ban {
+                id: user_id,
+                reason: Some("upstream spam".to_string()),
+                date: Utc::now(),
+                admin: 7,
+                message: None,
+                appeal_status: AppealStatus::None,
+                tags: vec![],
+                evidence: vec![],
+                language: None,
+                expires_at: None,
+                pinned: false,
+                active: true,
+            })
+        }
+    }
+
+    #[test]
+    fn test_upstream_fallback() {
+        let mut store = InMemoryStore::default();
+        let ban = routes::banlist::find_ban_or_upstream(&mut store, Some(&MockUpstream), 777001).unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("upstream spam"));
+        match routes::banlist::find_ban_or_upstream(&mut store, Some(&MockUpstream), 777002) {
+            Err(UserError::NotFound) => {}
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_whitelist_overrides_upstream() {
+        let mut store = InMemoryStore::default();
+        store.whitelist.insert(777001);
+        match routes::banlist::find_ban_or_upstream(&mut store, Some(&MockUpstream), 777001) {
+            Err(UserError::NotFound) => {}
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_remove_missing_ban() {
+        let mut store = InMemoryStore::default();
+        match routes::banlist::remove_ban(&mut store, 777000, 1) {
+            Err(UserError::NotFound) => {}
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_rejects_invalid_id() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 1, "reason": "spam"}, {"id": -100, "reason": "spam"}]));
+        match routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update) {
+            Err(UserError::BadRequest(_)) => {}
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+        assert_eq!(routes::banlist::banned_ids_body(&mut store).unwrap(), "1");
+    }
+
+    #[test]
+    fn test_evidence() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "spam", "evidence": ["https://t.me/c/1/2"]}
+        ]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+        assert_eq!(routes::banlist::find_ban(&mut store, 777000).unwrap().evidence, vec!["https://t.me/c/1/2"]);
+
+        let ban = routes::banlist::append_evidence(&mut store, 777000, "https://example.com/screenshot.png").unwrap();
+        assert_eq!(ban.evidence, vec!["https://t.me/c/1/2", "https://example.com/screenshot.png"]);
+        // The same link is only attached once
+        let ban = routes::banlist::append_evidence(&mut store, 777000, "https://t.me/c/1/2").unwrap();
+        assert_eq!(ban.evidence.len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_evidence() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "spam", "evidence": ["not a link"]}]));
+        match routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update) {
+            Err(UserError::BadRequest(_)) => {}
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+        match routes::banlist::append_evidence(&mut store, 777000, "https://t.me/c/1/2") {
+            Err(UserError::NotFound) => {}
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_control_only_reason() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "\u{1b}[0m\u{0}"}]));
+        match routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update) {
+            Err(UserError::BadRequest(_)) => {}
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_reason_sanitized_before_writing() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "\u{1b}[31mspam\u{1b}[0m"}]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+        assert_eq!(store.bans[&777000].reason.as_deref(), Some("spam"));
+    }
+
+    #[test]
+    fn test_banned_ids_sorted() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([
+            {"id": 5000000000i64, "reason": "spam"}, {"id": 12, "reason": "spam"}, {"id": 777000, "reason": "spam"}
+        ]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+        let ids: Vec<i64> = routes::banlist::banned_ids_body(&mut store).unwrap()
+            .lines()
+            .map(|id| id.parse().unwrap())
+            .collect();
+        assert_eq!(ids, vec![12, 777000, 5_000_000_000]);
+        assert!(ids.binary_search(&777000).is_ok());
+    }
+
+    #[test]
+    fn test_filter_by_language() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "spam", "language": "en"},
+            {"id": 777001, "reason": "спам", "language": "RU"},
+            {"id": 777002, "reason": "spam"}
+        ]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+
+        let russian = routes::banlist::find_bans_by_language(&mut store, BanFields::Full, "ru").unwrap();
+        assert_eq!(russian.iter().map(|ban| ban.id).collect::<Vec<i64>>(), vec![777001]);
+        let english = routes::banlist::find_bans_by_language(&mut store, BanFields::Full, "EN").unwrap();
+        assert_eq!(english.iter().map(|ban| ban.id).collect::<Vec<i64>>(), vec![777000]);
+        assert!(routes::banlist::find_bans_by_language(&mut store, BanFields::Full, "de").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_invalid_language() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "spam", "language": "english"}]));
+        match routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update) {
+            Err(UserError::BadRequest(_)) => {}
+            other => panic!("expected a bad request, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_sample_size() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([
+            {"id": 777000, "reason": "spam"}, {"id": 777001, "reason": "spam"}, {"id": 777002, "reason": "spam"}
+        ]));
+        routes::banlist::add_bans(&mut store, &new, 1, ConflictPolicy::Update).unwrap();
+
+        assert_eq!(routes::banlist::take_sample(&mut store, 2).unwrap().len(), 2);
+        // Asking for more than there are returns all of them
+        assert_eq!(routes::banlist::take_sample(&mut store, 10).unwrap().len(), 3);
+        assert!(routes::banlist::take_sample(&mut store, 0).is_err());
+        assert!(routes::banlist::take_sample(&mut store, routes::banlist::MAX_SAMPLE_SIZE + 1).is_err());
+    }
+
+    fn scoped_ban(reason: &str) -> routes::scopes::CreateScopedBan {
+        serde_json::from_value(serde_json::json!({"reason": reason})).unwrap()
+    }
+
+    #[test]
+    fn test_scoped_ban_round_trip() {
+        let mut store = InMemoryStore::default();
+        let ban = routes::scopes::add_scoped_ban(&mut store, -1001234, 777000, &scoped_ban("spam"), 1).unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("spam"));
+        routes::scopes::add_scoped_ban(&mut store, -1005678, 777000, &scoped_ban("flood"), 1).unwrap();
+
+        // The same user is banned separately per scope, and not globally
+        let ban = routes::scopes::find_scoped_ban(&mut store, -1005678, 777000).unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("flood"));
+        assert!(routes::banlist::find_ban(&mut store, 777000).is_err());
+
+        routes::scopes::remove_scoped_ban(&mut store, -1001234, 777000).unwrap();
+        assert!(routes::scopes::find_scoped_ban(&mut store, -1001234, 777000).is_err());
+        assert!(routes::scopes::find_scoped_ban(&mut store, -1005678, 777000).is_ok());
+        match routes::scopes::remove_scoped_ban(&mut store, -1001234, 777000) {
+            Err(UserError::NotFound) => {}
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_scoped_ban_overwrites_in_scope() {
+        let mut store = InMemoryStore::default();
+        routes::scopes::add_scoped_ban(&mut store, -1001234, 777000, &scoped_ban("spam"), 1).unwrap();
+        let ban = routes::scopes::add_scoped_ban(&mut store, -1001234, 777000, &scoped_ban("scam"), 2).unwrap();
+        assert_eq!(ban.reason.as_deref(), Some("scam"));
+        assert_eq!(ban.admin, 2);
+        assert_eq!(store.scoped.len(), 1);
+    }
+
+    #[test]
+    fn test_scoped_ban_validation() {
+        let mut store = InMemoryStore::default();
+        assert!(routes::scopes::add_scoped_ban(&mut store, 0, 777000, &scoped_ban("spam"), 1).is_err());
+        assert!(routes::scopes::add_scoped_ban(&mut store, -1001234, -5, &scoped_ban("spam"), 1).is_err());
+        assert!(routes::scopes::add_scoped_ban(&mut store, -1001234, 777000, &scoped_ban(""), 1).is_err());
+        assert!(store.scoped.is_empty());
+    }
+
+    #[test]
+    fn test_ban_admin() {
+        let mut store = InMemoryStore::default();
+        let new = bans(serde_json::json!([{"id": 777000, "reason": "spam"}]));
+        routes::banlist::add_bans(&mut store, &new, 7, ConflictPolicy::Update).unwrap();
+
+        assert_eq!(routes::banlist::find_ban_admin(&mut store, 777000).unwrap(), 7);
+        assert_eq!(store.get_ban_admin(777001).unwrap(), None);
+        match routes::banlist::find_ban_admin(&mut store, 777001) {
+            Err(UserError::NotFound) => {}
+            other => panic!("expected not found, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,73 @@
+#[cfg(test)]
+mod generate {
+    use crate::token_generator::{CHECKSUM_LENGTH, ChecksumGenerator, NanoidGenerator, TokenGenerator};
+
+    #[test]
+    fn test_nanoid() {
+        let generator = NanoidGenerator { size: 64 };
+        let token = generator.generate("");
+        assert_eq!(token.len(), 64);
+        assert!(generator.is_plausible(&token));
+    }
+
+    #[test]
+    fn test_prefix_and_checksum() {
+        let generator = ChecksumGenerator { prefix: "sw_".to_string(), size: 32 };
+        let token = generator.generate("");
+        assert!(token.starts_with("sw_"));
+        assert_eq!(token.len(), 3 + 32 + CHECKSUM_LENGTH);
+        assert!(generator.is_plausible(&token));
+        assert_ne!(token, generator.generate(""));
+    }
+}
+
+#[cfg(test)]
+mod checksum {
+    use crate::token_generator::{ChecksumGenerator, TokenGenerator};
+
+    fn generator() -> ChecksumGenerator {
+        ChecksumGenerator { prefix: "sw_".to_string(), size: 32 }
+    }
+
+    #[test]
+    fn test_tampered_token() {
+        let generator = generator();
+        let token = generator.generate("");
+        let mut tampered: Vec<char> = token.chars().collect();
+        tampered[5] = if tampered[5] == 'a' { 'b' } else { 'a' };
+        let tampered: String = tampered.into_iter().collect();
+        assert!(!generator.is_plausible(&tampered));
+        assert!(!generator.is_plausible(&token[..token.len() - 1]));
+        assert!(!generator.is_plausible("sw_"));
+        assert!(!generator.is_plausible("sw_ü"));
+    }
+
+    #[test]
+    fn test_legacy_token() {
+        // Issued by nanoid before the prefix was configured
+        assert!(generator().is_plausible("V1StGXR8_Z5jdHi6B-myT"));
+    }
+}
+
+#[cfg(test)]
+mod permission_prefix {
+    use crate::token_generator::{CHECKSUM_LENGTH, ChecksumGenerator, NanoidGenerator, TokenGenerator};
+
+    #[test]
+    fn test_root_prefix() {
+        let generator = ChecksumGenerator { prefix: "sw_".to_string(), size: 32 };
+        let token = generator.generate("root_");
+        assert!(token.starts_with("sw_root_"));
+        assert_eq!(token.len(), 3 + 5 + 32 + CHECKSUM_LENGTH);
+        assert!(generator.is_plausible(&token));
+        // The checksum covers the permission prefix too
+        assert!(!generator.is_plausible(&token.replacen("root_", "adm_", 1)));
+    }
+
+    #[test]
+    fn test_without_token_prefix() {
+        let token = NanoidGenerator { size: 32 }.generate("adm_");
+        assert!(token.starts_with("adm_"));
+        assert_eq!(token.len(), 4 + 32);
+    }
+}
@@ -46,6 +46,22 @@ mod get {
         let resp = test::block_on(app.call(req)).unwrap();
         assert_eq!(resp.status(), StatusCode::FORBIDDEN);
     }
+
+    #[test]
+    fn test_usage_report_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/tokens/usage")
+                    .route(web::get().to(routes::tokens::get_usage_report)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/tokens/usage?from=2026-01-01&to=2026-01-31").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
 
 #[cfg(test)]
@@ -76,6 +92,22 @@ mod post {
         let resp = test::block_on(app.call(req)).unwrap();
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[test]
+    fn test_rotate_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/tokens/{id}/rotate")
+                    .route(web::post().to(routes::tokens::rotate_token)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::post().uri("/tokens/1/rotate").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
 
 #[cfg(test)]
@@ -106,6 +138,44 @@ mod put {
         let resp = test::block_on(app.call(req)).unwrap();
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[test]
+    fn test_master_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/tokens/master")
+                    .route(web::put().to(routes::tokens::put_master)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::put()
+            .uri("/tokens/master")
+            .set_json(&serde_json::json!({"userid": 777000}))
+            .to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_note_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/tokens/{id}/note")
+                    .route(web::put().to(routes::tokens::put_token_note)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::put()
+            .uri("/tokens/2/note")
+            .set_json(&serde_json::json!({"note": "issued for migration"}))
+            .to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
 
 #[cfg(test)]
@@ -136,6 +206,25 @@ mod patch {
         let resp = test::block_on(app.call(req)).unwrap();
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[test]
+    fn test_permissions_by_userid_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/tokens/userid/{uid}")
+                    .route(web::patch().to(routes::tokens::patch_tokens_by_userid)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::patch()
+            .uri("/tokens/userid/777000")
+            .set_json(&serde_json::json!({"permission": "Admin"}))
+            .to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
 }
 
 #[cfg(test)]
@@ -166,4 +255,58 @@ mod delete {
         let resp = test::block_on(app.call(req)).unwrap();
         assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
     }
+
+    #[test]
+    fn test_delete_token_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/tokens/{id}")
+                    .route(web::delete().to(routes::tokens::delete_token)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::delete().uri("/tokens/2").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
+
+#[cfg(test)]
+mod note {
+    use crate::database::Token;
+    use crate::guards::Permission;
+    use crate::routes::tokens::{validate_note, MAX_NOTE_LENGTH};
+
+    fn token(note: Option<&str>) -> Token {
+        Token {
+            id: 2,
+            token: "token".to_string(),
+            permission: Permission::User,
+            userid: 777000,
+            retired: false,
+            last_used_at: None,
+            last_ip: None,
+            last_ip_changed_at: None,
+            note: note.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_note_only_for_admins() {
+        let token = token(Some("issued for migration, revoke after Q3"));
+        assert_eq!(token.json().unwrap()["note"], "issued for migration, revoke after Q3");
+        let holder = token.holder_json().unwrap();
+        assert!(holder.get("note").is_none());
+        assert_eq!(holder["userid"], 777000);
+        assert_eq!(holder["levels_to_root"], 2);
+    }
+
+    #[test]
+    fn test_validate_note() {
+        assert!(validate_note("issued for migration").is_ok());
+        assert!(validate_note(&"x".repeat(MAX_NOTE_LENGTH)).is_ok());
+        assert!(validate_note(&"x".repeat(MAX_NOTE_LENGTH + 1)).is_err());
+    }
 }
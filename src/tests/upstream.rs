@@ -0,0 +1,55 @@
+#[cfg(test)]
+mod http {
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::upstream::{HttpUpstream, Upstream};
+
+    // Answers a single request with `status` and `body`
+    fn mock_upstream(status: &'static str, body: &'static str) -> HttpUpstream {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request);
+            let response = format!("HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                                   status, body.len(), body);
+            stream.write_all(response.as_bytes()).unwrap();
+        });
+        HttpUpstream { url, token: "token".to_string(), cache_ttl: Duration::from_secs(60) }
+    }
+
+    #[test]
+    fn test_ban_from_upstream() {
+        let upstream = mock_upstream("200 OK", r#"{"id": 777000, "reason": "spam", "date": 1600000000, "admin": 3, "message": null}"#);
+        let ban = upstream.get_ban(777000).unwrap();
+        assert_eq!(ban.id, 777000);
+        assert_eq!(ban.reason.as_deref(), Some("spam"));
+        assert_eq!(ban.date.timestamp(), 1_600_000_000);
+        // The mock only answers once, so this comes from the cache
+        assert_eq!(upstream.get_ban(777000).unwrap().id, 777000);
+    }
+
+    #[test]
+    fn test_not_banned_upstream() {
+        let upstream = mock_upstream("404 Not Found", r#"{"code": 404, "error": "Not Found"}"#);
+        assert!(upstream.get_ban(777000).is_none());
+    }
+
+    #[test]
+    fn test_bogus_date_upstream() {
+        let upstream = mock_upstream("200 OK", r#"{"id": 777000, "reason": "spam", "date": 9223372036854775807, "admin": 3, "message": null}"#);
+        assert!(upstream.get_ban(777000).is_none());
+    }
+
+    #[test]
+    fn test_unreachable_upstream() {
+        // Bound and dropped right away, so nothing is listening
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let upstream = HttpUpstream { url: format!("http://{}", addr), token: String::new(), cache_ttl: Duration::from_secs(60) };
+        assert!(upstream.get_ban(777000).is_none());
+    }
+}
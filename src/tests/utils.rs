@@ -0,0 +1,256 @@
+#[cfg(test)]
+mod retry {
+    use crate::utils;
+
+    #[derive(Debug, PartialEq)]
+    enum StubError {
+        Collision,
+        Fatal,
+    }
+
+    #[test]
+    fn test_retry_after_collision() {
+        let mut calls = 0;
+        let result = utils::retry(5, |e| *e == StubError::Collision, || {
+            calls += 1;
+            if calls == 1 {
+                Err(StubError::Collision)
+            } else {
+                Ok(calls)
+            }
+        });
+        assert_eq!(result, Ok(2));
+    }
+
+    #[test]
+    fn test_retry_gives_up() {
+        let mut calls = 0;
+        let result: Result<(), StubError> = utils::retry(3, |e| *e == StubError::Collision, || {
+            calls += 1;
+            Err(StubError::Collision)
+        });
+        assert_eq!(result, Err(StubError::Collision));
+        assert_eq!(calls, 3);
+    }
+
+    #[test]
+    fn test_no_retry_on_other_errors() {
+        let mut calls = 0;
+        let result: Result<(), StubError> = utils::retry(3, |e| *e == StubError::Collision, || {
+            calls += 1;
+            Err(StubError::Fatal)
+        });
+        assert_eq!(result, Err(StubError::Fatal));
+        assert_eq!(calls, 1);
+    }
+}
+
+#[cfg(test)]
+mod id_list {
+    use crate::utils::parse_id_list;
+
+    #[test]
+    fn test_parse_id_list() {
+        assert_eq!(parse_id_list::<i32>("1,2, 3").unwrap(), vec![1, 2, 3]);
+        assert_eq!(parse_id_list::<i32>("7").unwrap(), vec![7]);
+    }
+
+    #[test]
+    fn test_parse_user_id_list() {
+        assert_eq!(parse_id_list::<i64>("777000,5000000000").unwrap(), vec![777000, 5_000_000_000]);
+    }
+
+    #[test]
+    fn test_invalid_id_list() {
+        assert!(parse_id_list::<i32>("1,a").is_err());
+        assert!(parse_id_list::<i32>("1,,2").is_err());
+    }
+}
+
+#[cfg(test)]
+mod dedup {
+    use std::time::{Duration, Instant};
+
+    use crate::utils::DedupWindow;
+
+    #[test]
+    fn test_duplicate_within_window() {
+        let mut dedup = DedupWindow::new(Duration::from_secs(5));
+        let now = Instant::now();
+        assert!(!dedup.is_duplicate(&1, &"spam", now));
+        dedup.record(1, "spam", now);
+        assert!(dedup.is_duplicate(&1, &"spam", now + Duration::from_secs(1)));
+    }
+
+    #[test]
+    fn test_changed_value() {
+        let mut dedup = DedupWindow::new(Duration::from_secs(5));
+        let now = Instant::now();
+        dedup.record(1, "spam", now);
+        assert!(!dedup.is_duplicate(&1, &"scam", now));
+        assert!(!dedup.is_duplicate(&2, &"spam", now));
+    }
+
+    #[test]
+    fn test_after_window() {
+        let mut dedup = DedupWindow::new(Duration::from_secs(5));
+        let now = Instant::now();
+        dedup.record(1, "spam", now);
+        assert!(!dedup.is_duplicate(&1, &"spam", now + Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_unrecorded_write() {
+        // A write that failed was never recorded, so the retry goes through
+        let mut dedup = DedupWindow::new(Duration::from_secs(5));
+        let now = Instant::now();
+        assert!(!dedup.is_duplicate(&1, &"spam", now));
+        assert!(!dedup.is_duplicate(&1, &"spam", now));
+    }
+
+    #[test]
+    fn test_forget() {
+        let mut dedup = DedupWindow::new(Duration::from_secs(5));
+        let now = Instant::now();
+        dedup.record(1, "spam", now);
+        dedup.forget(&1);
+        assert!(!dedup.is_duplicate(&1, &"spam", now));
+    }
+}
+
+#[cfg(test)]
+mod cursor {
+    use chrono::NaiveDate;
+
+    use crate::errors::UserError;
+    use crate::utils::{timestamp_from_secs_f64, timestamp_secs_f64};
+
+    #[test]
+    fn test_cursor_round_trip() {
+        let server_time = NaiveDate::from_ymd(2026, 10, 14).and_hms_micro(12, 30, 15, 123_456);
+        assert_eq!(timestamp_from_secs_f64(timestamp_secs_f64(&server_time)).unwrap(), server_time);
+    }
+
+    #[test]
+    fn test_whole_seconds() {
+        let date = timestamp_from_secs_f64(1_600_000_000.0).unwrap();
+        assert_eq!(date.timestamp(), 1_600_000_000);
+        assert_eq!(date.timestamp_subsec_micros(), 0);
+    }
+
+    #[test]
+    fn test_out_of_range() {
+        for secs in &[1e300, -1e20, f64::NAN, f64::INFINITY] {
+            match timestamp_from_secs_f64(*secs) {
+                Err(UserError::BadRequest(_)) => {}
+                other => panic!("expected a bad request for {}, got {:?}", secs, other),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod if_none_match {
+    use actix_web::test::TestRequest;
+
+    use crate::utils::if_none_match;
+
+    #[test]
+    fn test_matching_etag() {
+        let req = TestRequest::default().header("If-None-Match", "\"1-2\"").to_http_request();
+        assert!(if_none_match(&req, "\"1-2\""));
+        let req = TestRequest::default().header("If-None-Match", "\"0-0\", W/\"1-2\"").to_http_request();
+        assert!(if_none_match(&req, "\"1-2\""));
+        let req = TestRequest::default().header("If-None-Match", "*").to_http_request();
+        assert!(if_none_match(&req, "\"1-2\""));
+    }
+
+    #[test]
+    fn test_stale_etag() {
+        let req = TestRequest::default().header("If-None-Match", "\"1-1\"").to_http_request();
+        assert!(!if_none_match(&req, "\"1-2\""));
+        assert!(!if_none_match(&TestRequest::default().to_http_request(), "\"1-2\""));
+    }
+}
+
+#[cfg(test)]
+mod api_response {
+    use crate::database::Ban;
+    use crate::tests::database::sample_ban;
+    use crate::utils::ApiResponse;
+
+    fn ban(id: i64) -> Ban {
+        Ban { id, ..sample_ban() }
+    }
+
+    #[test]
+    fn test_ban_envelope() {
+        let bans = [ban(777000), ban(777001)];
+        let envelope = serde_json::to_value(ApiResponse::from_bans(&bans)).unwrap();
+        assert_eq!(envelope["count"], 2);
+        assert_eq!(envelope["data"], serde_json::json!([bans[0].raw_json(), bans[1].raw_json()]));
+        assert_eq!(envelope.as_object().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_empty_envelope() {
+        let envelope = serde_json::to_value(ApiResponse::<String>::new(vec![])).unwrap();
+        assert_eq!(envelope, serde_json::json!({"data": [], "count": 0}));
+    }
+}
+
+#[cfg(test)]
+mod read_only {
+    use actix_web::http::Method;
+
+    use crate::utils::is_write_request;
+
+    #[test]
+    fn test_reads_allowed() {
+        assert!(!is_write_request(&Method::GET, "/banlist"));
+        assert!(!is_write_request(&Method::HEAD, "/banlist/777000"));
+    }
+
+    #[test]
+    fn test_writes_blocked() {
+        assert!(is_write_request(&Method::POST, "/banlist"));
+        assert!(is_write_request(&Method::PUT, "/banlist/777000/pin"));
+        assert!(is_write_request(&Method::DELETE, "/tokens/2"));
+    }
+
+    #[test]
+    fn test_exempt_paths() {
+        assert!(!is_write_request(&Method::DELETE, "/lockdown"));
+        assert!(!is_write_request(&Method::POST, "/query"));
+    }
+}
+
+#[cfg(test)]
+mod escape_like {
+    use crate::utils::escape_like;
+
+    #[test]
+    fn test_escapes_wildcards() {
+        assert_eq!(escape_like("50%_off"), "50\\%\\_off");
+        assert_eq!(escape_like("back\\slash"), "back\\\\slash");
+        assert_eq!(escape_like("spam"), "spam");
+    }
+}
+
+#[cfg(test)]
+mod csv_field {
+    use crate::utils::csv_field;
+
+    #[test]
+    fn test_plain_field() {
+        assert_eq!(csv_field("spam"), "spam");
+        assert_eq!(csv_field(""), "");
+    }
+
+    #[test]
+    fn test_quoted_field() {
+        assert_eq!(csv_field("spam, scam"), "\"spam, scam\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("line\nbreak"), "\"line\nbreak\"");
+    }
+}
@@ -0,0 +1,57 @@
+#[cfg(test)]
+mod no_auth {
+    use actix_service::Service;
+    use actix_web::{App, web};
+    use actix_web::http::StatusCode;
+    use actix_web::test;
+
+    use crate::routes;
+
+    #[test]
+    fn test_get_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/whitelist/{id}")
+                    .route(web::get().to(routes::whitelist::get_whitelist)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::get().uri("/whitelist/777000").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_put_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/whitelist/{id}")
+                    .route(web::put().to(routes::whitelist::put_whitelist)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::put().uri("/whitelist/777000").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+
+    #[test]
+    fn test_delete_no_auth() {
+        let mut app = test::init_service(
+            App::new().service(
+                web::resource("/whitelist/{id}")
+                    .route(web::delete().to(routes::whitelist::delete_whitelist)),
+            ),
+        );
+        // Create request object
+        let req = test::TestRequest::delete().uri("/whitelist/777000").to_request();
+
+        // Execute application
+        let resp = test::block_on(app.call(req)).unwrap();
+        assert_eq!(resp.status(), StatusCode::UNAUTHORIZED);
+    }
+}
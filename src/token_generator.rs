@@ -0,0 +1,84 @@
+use lazy_static::lazy_static;
+
+use crate::guards::Permission;
+use crate::settings;
+
+const CHECKSUM_ALPHABET: &[u8] = b"_-0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+pub const CHECKSUM_LENGTH: usize = 4;
+
+lazy_static! {
+    pub static ref GENERATOR: Box<dyn TokenGenerator + Send + Sync> = from_settings(&settings::ENV.general);
+}
+
+pub trait TokenGenerator {
+    // `permission_prefix` is one of permission_prefixes, it goes right after general.token_prefix
+    fn generate(&self, permission_prefix: &str) -> String;
+    // False if this token can't have been issued, so it's refused without asking the database
+    fn is_plausible(&self, token: &str) -> bool;
+}
+
+pub struct NanoidGenerator {
+    pub size: usize,
+}
+
+// `<prefix><permission prefix><nanoid><checksum>`, the checksum covers everything before it
+pub struct ChecksumGenerator {
+    pub prefix: String,
+    pub size: usize,
+}
+
+pub fn from_settings(general: &settings::General) -> Box<dyn TokenGenerator + Send + Sync> {
+    let size = general.token_size as usize;
+    if general.token_prefix.is_empty() {
+        Box::new(NanoidGenerator { size })
+    } else {
+        Box::new(ChecksumGenerator { prefix: general.token_prefix.clone(), size })
+    }
+}
+
+pub fn generate(permission: &Permission) -> String {
+    GENERATOR.generate(settings::ENV.permission_prefixes.for_permission(permission))
+}
+
+pub fn is_plausible(token: &str) -> bool {
+    GENERATOR.is_plausible(token)
+}
+
+/// FNV-1a over the token, four characters of the alphabet taken from the low 24 bits
+pub fn checksum(token: &str) -> String {
+    let hash = token.bytes().fold(0x811c_9dc5_u32, |hash, byte| (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193));
+    (0..CHECKSUM_LENGTH)
+        .map(|i| CHECKSUM_ALPHABET[((hash >> (6 * i)) & 63) as usize] as char)
+        .collect()
+}
+
+impl TokenGenerator for NanoidGenerator {
+    fn generate(&self, permission_prefix: &str) -> String {
+        format!("{}{}", permission_prefix, nanoid::generate(self.size))
+    }
+
+    fn is_plausible(&self, _token: &str) -> bool {
+        true
+    }
+}
+
+impl TokenGenerator for ChecksumGenerator {
+    fn generate(&self, permission_prefix: &str) -> String {
+        let token = format!("{}{}{}", self.prefix, permission_prefix, nanoid::generate(self.size));
+        let checksum = checksum(&token);
+        token + &checksum
+    }
+
+    fn is_plausible(&self, token: &str) -> bool {
+        // Tokens issued before the prefix was configured have neither prefix nor checksum
+        let random = match token.strip_prefix(self.prefix.as_str()) {
+            Some(random) => random,
+            None => return true,
+        };
+        if random.len() <= CHECKSUM_LENGTH {
+            return false;
+        }
+        let split = token.len() - CHECKSUM_LENGTH;
+        token.is_char_boundary(split) && checksum(&token[..split]) == token[split..]
+    }
+}
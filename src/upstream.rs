@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+use crate::database::{AppealStatus, Ban};
+use crate::settings;
+use crate::utils;
+
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(2);
+// Expired answers are only dropped once the cache grows past this
+const MAX_CACHED_ANSWERS: usize = 10_000;
+
+// When the upstream answered and what, keyed on (upstream url, user id)
+type Answers = HashMap<(String, i64), (Instant, Option<Ban>)>;
+
+lazy_static! {
+    // Misses are cached too, so an id nobody bans doesn't reach the upstream on every lookup
+    static ref CACHE: Mutex<Answers> = Mutex::new(HashMap::new());
+}
+
+/// Another banlist that is asked when a ban isn't found locally
+pub trait Upstream {
+    // None if the upstream has no ban for the id or couldn't be asked
+    fn get_ban(&self, user_id: i64) -> Option<Ban>;
+}
+
+// A ban as the SpamWatch API returns it
+#[derive(Deserialize)]
+struct UpstreamBan {
+    id: i64,
+    reason: Option<String>,
+    date: i64,
+    admin: i32,
+    message: Option<String>,
+}
+
+impl TryFrom<UpstreamBan> for Ban {
+    type Error = String;
+
+    fn try_from(ban: UpstreamBan) -> Result<Ban, String> {
+        let date = NaiveDateTime::from_timestamp_opt(ban.date, 0)
+            .ok_or_else(|| format!("ban date {} is out of range", ban.date))?;
+        Ok(Ban {
+            id: ban.id,
+            reason: ban.reason,
+            date: DateTime::from_utc(date, Utc),
+            admin: ban.admin,
+            message: ban.message,
+            appeal_status: AppealStatus::None,
+            tags: vec![],
+            evidence: vec![],
+            language: None,
+            expires_at: None,
+            pinned: false,
+            active: true,
+        })
+    }
+}
+
+pub struct HttpUpstream {
+    pub url: String,
+    pub token: String,
+    pub cache_ttl: Duration,
+}
+
+pub fn from_settings() -> Option<HttpUpstream> {
+    let upstream = &settings::ENV.upstream;
+    if upstream.url.is_empty() {
+        return None;
+    }
+    Some(HttpUpstream {
+        url: upstream.url.trim_end_matches('/').to_string(),
+        token: upstream.token.clone(),
+        cache_ttl: Duration::from_secs(upstream.cache_seconds),
+    })
+}
+
+impl HttpUpstream {
+    fn fetch(&self, user_id: i64) -> Result<Option<Ban>, String> {
+        let response = ureq::get(&format!("{}/banlist/{}", self.url, user_id))
+            .timeout(UPSTREAM_TIMEOUT)
+            .set("Authorization", &format!("Bearer {}", self.token))
+            .call();
+        match response {
+            Ok(response) => {
+                let body = response.into_string().map_err(|e| e.to_string())?;
+                let ban: UpstreamBan = serde_json::from_str(&body).map_err(|e| e.to_string())?;
+                Ok(Some(Ban::try_from(ban)?))
+            }
+            Err(ureq::Error::Status(404, _)) => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+impl Upstream for HttpUpstream {
+    fn get_ban(&self, user_id: i64) -> Option<Ban> {
+        let key = (self.url.clone(), user_id);
+        if let Some((at, ban)) = CACHE.lock().unwrap().get(&key) {
+            if at.elapsed() < self.cache_ttl {
+                return ban.clone();
+            }
+        }
+        match self.fetch(user_id) {
+            Ok(ban) => {
+                let mut cache = CACHE.lock().unwrap();
+                if cache.len() >= MAX_CACHED_ANSWERS {
+                    let ttl = self.cache_ttl;
+                    cache.retain(|_, (at, _)| at.elapsed() < ttl);
+                }
+                cache.insert(key, (Instant::now(), ban.clone()));
+                ban
+            }
+            // Not cached, the next lookup tries again
+            Err(e) => {
+                warn!(utils::LOGGER, "Upstream banlist unavailable"; "id" => user_id, "error" => e);
+                None
+            }
+        }
+    }
+}
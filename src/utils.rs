@@ -1,10 +1,21 @@
-use actix_web::HttpRequest;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use actix_web::{HttpRequest, HttpResponse};
+use actix_web::http::Method;
+use chrono::{DateTime, NaiveDateTime, Utc};
 use lazy_static::lazy_static;
+use serde::{Serialize, Serializer};
+use serde_json::Value;
 use slog::{Drain, Logger};
 use slog_async;
 use slog_term;
 
+use crate::database::Ban;
 use crate::errors::UserError;
+use crate::settings;
 
 fn logger() -> Logger {
     let decorator = slog_term::TermDecorator::new().force_color().build();
@@ -30,3 +41,166 @@ pub fn get_auth_token(req: &HttpRequest) -> Result<String, UserError> {
     let _token: Vec<&str> = token_header.split_ascii_whitespace().collect();
     Ok(_token.get(1).ok_or(UserError::BadRequest("could not find token. is it prefixed with `Bearer` ?"))?.to_string())
 }
+
+pub fn parse_id_list<T: FromStr>(ids: &str) -> Result<Vec<T>, UserError> {
+    ids.split(',')
+        .map(|id| id.trim().parse().map_err(|_| {
+            UserError::BadRequest("could not convert id list to integers")
+        }))
+        .collect()
+}
+
+pub fn retry<T, E>(
+    attempts: u32,
+    should_retry: impl Fn(&E) -> bool,
+    mut op: impl FnMut() -> Result<T, E>,
+) -> Result<T, E> {
+    let mut attempt = 1;
+    loop {
+        match op() {
+            Err(ref e) if attempt < attempts && should_retry(e) => {
+                warn!(LOGGER, "Retrying operation"; "attempt" => attempt);
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+pub fn serialize_timestamp_opt<S: Serializer>(
+    date: &Option<NaiveDateTime>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match date {
+        Some(date) => serializer.serialize_some(&date.timestamp()),
+        None => serializer.serialize_none(),
+    }
+}
+
+// Paths that keep working in read-only mode even though they aren't GET requests
+const READ_ONLY_EXEMPT: [&str; 2] = ["/lockdown", "/query"];
+
+pub fn is_write_request(method: &Method, path: &str) -> bool {
+    let read = *method == Method::GET || *method == Method::HEAD || *method == Method::OPTIONS;
+    !read && !READ_ONLY_EXEMPT.contains(&path)
+}
+
+// Quotes a CSV field if it would otherwise split the row
+pub fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+// Makes user input match literally in a LIKE pattern
+pub fn escape_like(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+    for c in input.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+pub fn if_none_match(req: &HttpRequest, etag: &str) -> bool {
+    match req.headers().get("if-none-match").and_then(|v| v.to_str().ok()) {
+        Some(value) => value.split(',').any(|tag| {
+            let tag = tag.trim();
+            tag == "*" || tag.trim_start_matches("W/") == etag
+        }),
+        None => false,
+    }
+}
+
+/// Seconds since the epoch with microsecond precision, for cursors that must not skip or repeat rows
+pub fn timestamp_secs_f64(date: &NaiveDateTime) -> f64 {
+    date.timestamp() as f64 + f64::from(date.timestamp_subsec_micros()) / 1e6
+}
+
+// Cursors come from clients, so anything chrono can't represent is a bad request rather than a panic
+pub fn timestamp_from_secs_f64(secs: f64) -> Result<NaiveDateTime, UserError> {
+    let out_of_range = UserError::BadRequest("timestamp is out of range");
+    if !secs.is_finite() {
+        return Err(out_of_range);
+    }
+    let whole = secs.floor();
+    let micros = ((secs - whole) * 1e6).round() as i64;
+    NaiveDateTime::from_timestamp_opt(whole as i64, 0)
+        .and_then(|date| date.checked_add_signed(chrono::Duration::microseconds(micros)))
+        .ok_or(out_of_range)
+}
+
+pub fn serialize_timestamp_f64<S: Serializer>(
+    date: &NaiveDateTime,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_f64(timestamp_secs_f64(date))
+}
+
+pub fn serialize_datetime_opt<S: Serializer>(
+    date: &Option<DateTime<Utc>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match date {
+        Some(date) => serializer.serialize_some(&date.timestamp()),
+        None => serializer.serialize_none(),
+    }
+}
+
+pub struct DedupWindow<K, V> {
+    window: Duration,
+    seen: HashMap<K, (V, Instant)>,
+}
+
+impl<K: Eq + Hash, V: PartialEq> DedupWindow<K, V> {
+    pub fn new(window: Duration) -> Self {
+        DedupWindow { window, seen: HashMap::new() }
+    }
+
+    // True if the same value was recorded for `key` within the window
+    pub fn is_duplicate(&mut self, key: &K, value: &V, now: Instant) -> bool {
+        let window = self.window;
+        self.seen.retain(|_, (_, seen_at)| now.duration_since(*seen_at) < window);
+        matches!(self.seen.get(key), Some((seen, _)) if seen == value)
+    }
+
+    // Only call this once the value was actually written, a failed write has to be retried
+    pub fn record(&mut self, key: K, value: V, now: Instant) {
+        self.seen.insert(key, (value, now));
+    }
+
+    pub fn forget(&mut self, key: &K) {
+        self.seen.remove(key);
+    }
+}
+
+// Envelope for list endpoints, only sent when general.envelope_lists is set so existing clients keep working
+#[derive(Debug, Serialize)]
+pub struct ApiResponse<T> {
+    pub data: Vec<T>,
+    pub count: usize,
+}
+
+impl<T: Serialize> ApiResponse<T> {
+    pub fn new(data: Vec<T>) -> ApiResponse<T> {
+        ApiResponse { count: data.len(), data }
+    }
+
+    pub fn respond(self) -> HttpResponse {
+        if settings::ENV.general.envelope_lists {
+            HttpResponse::Ok().json(self)
+        } else {
+            HttpResponse::Ok().json(self.data)
+        }
+    }
+}
+
+impl ApiResponse<Value> {
+    pub fn from_bans(bans: &[Ban]) -> ApiResponse<Value> {
+        ApiResponse::new(bans.iter().map(|ban| ban.raw_json()).collect())
+    }
+}